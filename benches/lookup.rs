@@ -0,0 +1,91 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::hash_map::RandomState;
+
+use quickscope::ScopeMap;
+
+/// Builds a map with `depth` layers, where `base` is only defined in the base layer
+/// and `top` is defined in the base layer and shadowed in the top layer.
+fn deep_map(depth: usize) -> ScopeMap<&'static str, usize> {
+  let mut map = ScopeMap::new();
+  map.define("base", 0);
+  map.define("top", 0);
+  for i in 1..depth {
+    map.push_layer();
+    map.define("filler", i);
+  }
+  map.define("top", depth);
+  map
+}
+
+fn bench_depth_queries(c: &mut Criterion) {
+  let mut group = c.benchmark_group("depth_queries");
+  for depth in [10, 100, 1000] {
+    let map = deep_map(depth);
+    group.bench_with_input(BenchmarkId::new("depth_of", depth), &map, |b, map| {
+      b.iter(|| map.depth_of(black_box("base")))
+    });
+    group.bench_with_input(BenchmarkId::new("height_of", depth), &map, |b, map| {
+      b.iter(|| map.height_of(black_box("base")))
+    });
+    group.bench_with_input(BenchmarkId::new("get_parent", depth), &map, |b, map| {
+      b.iter(|| map.get_parent(black_box("top"), black_box(depth / 2)))
+    });
+    group.bench_with_input(BenchmarkId::new("get_parent_depth", depth), &map, |b, map| {
+      b.iter(|| map.get_parent_depth(black_box("top"), black_box(depth / 2)))
+    });
+  }
+  group.finish();
+}
+
+fn bench_define_parent(c: &mut Criterion) {
+  let mut group = c.benchmark_group("define_parent");
+  for depth in [10, 100, 1000] {
+    group.bench_function(BenchmarkId::from_parameter(depth), |b| {
+      b.iter_batched_ref(
+        || deep_map(depth),
+        |map| map.define_parent(black_box("top"), 1, black_box(depth / 2)),
+        criterion::BatchSize::SmallInput,
+      )
+    });
+  }
+  group.finish();
+}
+
+/// Runs a REPL-like workload: `depth` nested layers, each shadowing the same few keys.
+fn shadowing_workload<const STACK: usize, const LAYERS: usize>(depth: usize) -> usize {
+  let mut map: ScopeMap<usize, usize, RandomState, STACK, LAYERS> = Default::default();
+  let mut sum = 0;
+  for layer in 0..depth {
+    for key in 0..4 {
+      map.define(key, layer);
+    }
+    for key in 0..4 {
+      sum += map.get(&key).copied().unwrap_or_default();
+    }
+    map.push_layer();
+  }
+  while map.pop_layer() {}
+  sum
+}
+
+fn bench_inline_capacity(c: &mut Criterion) {
+  let mut group = c.benchmark_group("inline_capacity");
+  for depth in [8, 32] {
+    group.bench_function(BenchmarkId::new("stack_1_layers_1", depth), |b| {
+      b.iter(|| shadowing_workload::<1, 1>(black_box(depth)))
+    });
+    group.bench_function(BenchmarkId::new("stack_4_layers_1", depth), |b| {
+      b.iter(|| shadowing_workload::<4, 1>(black_box(depth)))
+    });
+    group.bench_function(BenchmarkId::new("stack_1_layers_8", depth), |b| {
+      b.iter(|| shadowing_workload::<1, 8>(black_box(depth)))
+    });
+    group.bench_function(BenchmarkId::new("stack_8_layers_8", depth), |b| {
+      b.iter(|| shadowing_workload::<8, 8>(black_box(depth)))
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, bench_depth_queries, bench_define_parent, bench_inline_capacity);
+criterion_main!(benches);
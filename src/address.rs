@@ -0,0 +1,26 @@
+/// A frame-relative location of a binding in a [`ScopeMap`](crate::ScopeMap), as returned by `resolve()`.
+///
+/// Each layer assigns dense slot numbers to its bindings, starting at 0. Redefining a key in the same layer keeps its slot.
+/// Removing a binding leaves its slot vacant without moving any other binding, and a later definition in the same layer
+/// reuses the most recently vacated slot. An address also identifies the binding it was resolved to,
+/// so an address of a removed binding resolves to nothing rather than to the binding that reused its slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address {
+  /// The number of layers between the topmost layer and the layer containing the binding.
+  pub depth: usize,
+  /// The position of the binding within its layer.
+  pub slot: usize,
+  /// The stamp of the binding the address was resolved to.
+  pub(crate) stamp: usize,
+}
+
+impl Address {
+  #[inline]
+  pub(crate) fn new(depth: usize, slot: usize, stamp: usize) -> Self {
+    Self {
+      depth,
+      slot,
+      stamp,
+    }
+  }
+}
@@ -0,0 +1,197 @@
+use std::{collections::HashSet, hash::{BuildHasher, Hash}};
+
+use crate::ScopeSet;
+
+/// A set of keys that supports set algebra with the visible keys of a [`ScopeSet`].
+///
+/// Implemented for `ScopeSet` (considering all visible keys), for [`TopLayer`] (considering only the keys in a set's topmost layer),
+/// and for `HashSet`, so that any of them can be combined with any other.
+///
+/// The operations are named so that they aren't shadowed by the inherent methods of `HashSet`.
+pub trait KeySet<T> {
+  /// Iterates over the keys in the set in arbitrary order.
+  fn iter_keys<'a>(&'a self) -> impl Iterator<Item = &'a T>
+  where
+    T: 'a;
+
+  /// Returns `true` if the set contains `key`.
+  fn has_key(&self, key: &T) -> bool;
+
+  /// Iterates over the keys that are in `self` or `other`, without duplicates.
+  #[inline]
+  fn union_keys<'a, O: KeySet<T> + ?Sized>(&'a self, other: &'a O) -> impl Iterator<Item = &'a T>
+  where
+    T: 'a,
+  {
+    self.iter_keys().chain(other.difference_keys(self))
+  }
+
+  /// Iterates over the keys that are in both `self` and `other`.
+  #[inline]
+  fn intersection_keys<'a, O: KeySet<T> + ?Sized>(&'a self, other: &'a O) -> impl Iterator<Item = &'a T>
+  where
+    T: 'a,
+  {
+    self.iter_keys().filter(move |key| other.has_key(key))
+  }
+
+  /// Iterates over the keys that are in `self` but not in `other`.
+  #[inline]
+  fn difference_keys<'a, O: KeySet<T> + ?Sized>(&'a self, other: &'a O) -> impl Iterator<Item = &'a T>
+  where
+    T: 'a,
+  {
+    self.iter_keys().filter(move |key| !other.has_key(key))
+  }
+
+  /// Iterates over the keys that are in exactly one of `self` and `other`.
+  #[inline]
+  fn symmetric_difference_keys<'a, O: KeySet<T> + ?Sized>(&'a self, other: &'a O) -> impl Iterator<Item = &'a T>
+  where
+    T: 'a,
+  {
+    self.difference_keys(other).chain(other.difference_keys(self))
+  }
+
+  /// Returns `true` if every key in `self` is also in `other`.
+  #[inline]
+  fn is_key_subset<O: KeySet<T> + ?Sized>(&self, other: &O) -> bool {
+    self.iter_keys().all(|key| other.has_key(key))
+  }
+
+  /// Returns `true` if `self` and `other` have no keys in common.
+  #[inline]
+  fn is_key_disjoint<O: KeySet<T> + ?Sized>(&self, other: &O) -> bool {
+    self.iter_keys().all(|key| !other.has_key(key))
+  }
+}
+
+/// A view of the keys in the topmost layer of a [`ScopeSet`], created by [`ScopeSet::top`].
+///
+/// Use it with the [`KeySet`] operations to restrict them to the topmost layer.
+pub struct TopLayer<'a, T, S: BuildHasher, const STACK: usize = 1, const LAYERS: usize = 1> {
+  set: &'a ScopeSet<T, S, STACK, LAYERS>,
+}
+
+impl<'a, T, S: BuildHasher, const STACK: usize, const LAYERS: usize> Clone for TopLayer<'a, T, S, STACK, LAYERS> {
+  #[inline]
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<'a, T, S: BuildHasher, const STACK: usize, const LAYERS: usize> Copy for TopLayer<'a, T, S, STACK, LAYERS> {}
+
+impl<T: Eq + Hash, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeSet<T, S, STACK, LAYERS> {
+  /// Gets a view of the keys in the topmost layer, for use with the [`KeySet`] operations.
+  #[inline]
+  pub fn top(&self) -> TopLayer<'_, T, S, STACK, LAYERS> {
+    TopLayer {
+      set: self,
+    }
+  }
+}
+
+impl<T: Eq + Hash, S: BuildHasher, const STACK: usize, const LAYERS: usize> KeySet<T> for ScopeSet<T, S, STACK, LAYERS> {
+  #[inline]
+  fn iter_keys<'a>(&'a self) -> impl Iterator<Item = &'a T>
+  where
+    T: 'a,
+  {
+    self.iter()
+  }
+
+  #[inline]
+  fn has_key(&self, key: &T) -> bool {
+    self.contains(key)
+  }
+}
+
+impl<'s, T: Eq + Hash, S: BuildHasher, const STACK: usize, const LAYERS: usize> KeySet<T> for TopLayer<'s, T, S, STACK, LAYERS> {
+  #[inline]
+  fn iter_keys<'a>(&'a self) -> impl Iterator<Item = &'a T>
+  where
+    T: 'a,
+  {
+    self.set.iter_top()
+  }
+
+  #[inline]
+  fn has_key(&self, key: &T) -> bool {
+    self.set.contains_at_top(key)
+  }
+}
+
+impl<T: Eq + Hash, S: BuildHasher> KeySet<T> for HashSet<T, S> {
+  #[inline]
+  fn iter_keys<'a>(&'a self) -> impl Iterator<Item = &'a T>
+  where
+    T: 'a,
+  {
+    self.iter()
+  }
+
+  #[inline]
+  fn has_key(&self, key: &T) -> bool {
+    self.contains(key)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn sorted<'a>(keys: impl Iterator<Item = &'a &'static str>) -> Vec<&'static str> {
+    let mut keys: Vec<_> = keys.copied().collect();
+    keys.sort_unstable();
+    keys
+  }
+
+  fn sample() -> (ScopeSet<&'static str>, ScopeSet<&'static str>) {
+    let mut a = ScopeSet::new();
+    a.define("x");
+    a.push_layer();
+    a.define("y");
+    a.define("z");
+    let mut b = ScopeSet::new();
+    b.define("y");
+    b.push_layer();
+    b.define("w");
+    (a, b)
+  }
+
+  #[test]
+  fn algebra_visible_keys() {
+    let (a, b) = sample();
+    assert_eq!(vec!["w", "x", "y", "z"], sorted(a.union_keys(&b)));
+    assert_eq!(vec!["y"], sorted(a.intersection_keys(&b)));
+    assert_eq!(vec!["x", "z"], sorted(a.difference_keys(&b)));
+    assert_eq!(vec!["w", "x", "z"], sorted(a.symmetric_difference_keys(&b)));
+    assert!(!a.is_key_disjoint(&b));
+    assert!(!a.is_key_subset(&b));
+    assert!(a.intersection_keys(&b).all(|key| b.contains(key)));
+  }
+
+  #[test]
+  fn algebra_top_layer() {
+    let (a, b) = sample();
+    assert_eq!(vec!["y", "z"], sorted(a.top().iter_keys()));
+    assert!(a.top().is_key_disjoint(&b.top()));
+    assert_eq!(vec!["y"], sorted(a.top().intersection_keys(&b)));
+    assert_eq!(vec!["x"], sorted(a.difference_keys(&a.top())));
+    assert!(a.top().is_key_subset(&a));
+  }
+
+  #[test]
+  fn algebra_hash_set() {
+    let (a, _) = sample();
+    let live: HashSet<&str> = ["x", "q"].iter().copied().collect();
+    assert_eq!(vec!["x"], sorted(a.intersection_keys(&live)));
+    assert_eq!(vec!["q", "x", "y", "z"], sorted(a.union_keys(&live)));
+    assert!(!a.is_key_subset(&live));
+    let all: HashSet<&str> = a.iter().copied().collect();
+    assert!(a.is_key_subset(&all));
+    assert_eq!(vec!["q"], sorted(live.difference_keys(&a)));
+    assert!(live.is_key_subset(&a.union_keys(&live).copied().collect::<HashSet<_>>()));
+  }
+}
@@ -0,0 +1,342 @@
+use std::{borrow::Borrow, hash::{BuildHasher, Hash}, sync::Arc};
+
+use crate::{overlay::Overlay, ScopeMap};
+
+/// A [`ScopeMap`] layered on top of a shared parent map, created by [`ScopeMap::with_parent`].
+///
+/// Lookups that miss in the child's own layers fall through to the parent.
+/// The parent's layers sit below the child's own layers: heights count up from the parent's base layer,
+/// so a binding found in the parent always has a lower height (and greater depth) than any of the child's own bindings.
+/// A new child has one layer of its own, which can't be popped.
+///
+/// Changes never reach into the parent's layers. `define_parent()` saturates to the child's first layer, and getting mutable access
+/// to a binding found in the parent first copies that binding into the child's first layer, where it shadows the parent's binding.
+/// The parent itself is never modified or cloned, and can be shared between many children, including across threads.
+///
+/// [`ForkedScopeMap`](crate::ForkedScopeMap) follows the same conventions, but starts without any layers of its own.
+#[derive(Clone)]
+pub struct ChildScopeMap<K, V, S: BuildHasher, const STACK: usize = 1, const LAYERS: usize = 1> {
+  inner: Overlay<K, V, S, STACK, LAYERS>,
+}
+
+impl<K, V, S: Clone + BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Creates a map with one empty layer whose lookups fall through to `parent`.
+  ///
+  /// The new map uses the parent's hasher, redefinition policy and read tracking setting.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn with_parent(parent: Arc<ScopeMap<K, V, S, STACK, LAYERS>>) -> ChildScopeMap<K, V, S, STACK, LAYERS> {
+    ChildScopeMap {
+      inner: Overlay::new(parent, 1),
+    }
+  }
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ChildScopeMap<K, V, S, STACK, LAYERS> {
+  /// Gets the parent map.
+  #[inline]
+  pub fn parent(&self) -> &Arc<ScopeMap<K, V, S, STACK, LAYERS>> {
+    self.inner.base()
+  }
+
+  /// Gets the number of layers in the map, including the parent's layers.
+  #[inline]
+  pub fn depth(&self) -> usize {
+    self.inner.depth()
+  }
+
+  /// Gets the number of layers that belong to the child, which is always at least 1.
+  #[inline]
+  pub fn own_depth(&self) -> usize {
+    self.inner.own_depth()
+  }
+
+  /// Adds a new, empty layer owned by the child.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn push_layer(&mut self) {
+    self.inner.push_layer()
+  }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone, S: BuildHasher, const STACK: usize, const LAYERS: usize> ChildScopeMap<K, V, S, STACK, LAYERS> {
+  /// Gets a mutable reference to the topmost value associated with a key.
+  ///
+  /// If the binding is in the parent, it is first copied into the child's first layer, leaving the parent unchanged.
+  #[inline]
+  pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.get_mut_own(key)
+  }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone, S: Clone + BuildHasher, const STACK: usize, const LAYERS: usize> ChildScopeMap<K, V, S, STACK, LAYERS> {
+  /// Converts the child into a standalone `ScopeMap` with the same layers and bindings, including the parent's.
+  ///
+  /// The parent is cloned if it is still shared with other maps.
+  #[inline]
+  pub fn into_map(self) -> ScopeMap<K, V, S, STACK, LAYERS> {
+    self.inner.into_map()
+  }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ChildScopeMap<K, V, S, STACK, LAYERS> {
+  /// Gets the number of unique keys in the map, including keys only defined in the parent.
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.inner.len()
+  }
+
+  /// Returns `true` if neither the child nor the parent contains any keys.
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Removes the topmost layer (if it isn't the child's first layer) and all associated keys/values.
+  /// Returns `true` if a layer was removed.
+  #[inline]
+  pub fn pop_layer(&mut self) -> bool {
+    self.inner.pop_own_layer()
+  }
+
+  /// Adds the specified entry to the topmost layer.
+  #[inline]
+  pub fn define(&mut self, key: K, value: V) {
+    self.inner.define_own(key, value)
+  }
+
+  /// Adds the specified entry in the layer `min_depth` layers from the top. Saturates to the child's first layer.
+  #[inline]
+  pub fn define_parent(&mut self, key: K, value: V, min_depth: usize) {
+    self.inner.define_parent_own(key, value, min_depth)
+  }
+
+  /// Removes the entry with the specified key from the topmost layer and returns its value.
+  #[inline]
+  pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.remove_own(key)
+  }
+
+  /// Removes all entries in the topmost layer.
+  #[inline]
+  pub fn clear_top(&mut self) {
+    self.inner.clear_own_top()
+  }
+
+  /// Removes all of the child's own entries and additional layers, leaving it with one empty layer. The parent is unaffected.
+  #[inline]
+  pub fn clear_all(&mut self) {
+    self.inner.clear_own()
+  }
+
+  /// Returns `true` if the map or its parent contains the specified key in any layer.
+  #[inline]
+  pub fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.contains_key(key)
+  }
+
+  /// Returns `true` if the map contains the specified key at the top layer.
+  #[inline]
+  pub fn contains_key_at_top<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.contains_key_at_top(key)
+  }
+
+  /// Gets a reference to the topmost value associated with a key, falling back to the parent.
+  #[inline]
+  pub fn get<Q>(&self, key: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.get(key)
+  }
+
+  /// Gets a reference to the value associated with a key at least `min_depth` layers below the topmost layer,
+  /// falling back to the parent.
+  #[inline]
+  pub fn get_parent<Q>(&self, key: &Q, min_depth: usize) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.get_parent(key, min_depth)
+  }
+
+  /// Gets the height of the specified key (i.e. how many layers up from the parent's bottom layer that the key last appears).
+  ///
+  /// Bindings found in the parent have heights below the child's own layers.
+  #[inline]
+  pub fn height_of<Q>(&self, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.height_of(key)
+  }
+
+  /// Gets the depth of the specified key (i.e. how many layers down from the top that the key first appears).
+  ///
+  /// Bindings found in the parent have depths below the child's own layers.
+  #[inline]
+  pub fn depth_of<Q>(&self, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.depth_of(key)
+  }
+
+  /// Iterates over all key-value pairs visible in the child or the parent, in arbitrary order.
+  ///
+  /// The iterator element type is `(&'a K, &'a V)`.
+  #[inline]
+  pub fn iter(&self) -> impl Iterator<Item = (&'_ K, &'_ V)> {
+    self.inner.iter()
+  }
+
+  /// Iterates over all keys visible in the child or the parent, in arbitrary order.
+  ///
+  /// The iterator element type is `&'a K`.
+  #[inline]
+  pub fn keys(&self) -> impl Iterator<Item = &'_ K> {
+    self.iter().map(|(key, _)| key)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::thread;
+
+  fn builtins() -> Arc<ScopeMap<&'static str, i32>> {
+    let mut map = ScopeMap::new();
+    map.define("print", 1);
+    map.define("len", 2);
+    Arc::new(map)
+  }
+
+  #[test]
+  fn child_falls_through_to_parent() {
+    let mut child = ScopeMap::with_parent(builtins());
+    child.define("x", 10);
+    child.push_layer();
+    child.define("len", 20);
+    assert_eq!(3, child.depth());
+    assert_eq!(Some(&1), child.get("print"));
+    assert_eq!(Some(&20), child.get("len"));
+    assert_eq!(Some(&2), child.get_parent("len", 1));
+    assert_eq!(Some(&2), child.get_parent("len", 2));
+    assert_eq!(None, child.get_parent("x", 2));
+    assert_eq!(3, child.len());
+    assert!(child.remove("len").is_some());
+    assert_eq!(Some(&2), child.get("len"));
+    assert!(child.pop_layer());
+    assert!(!child.pop_layer());
+    assert_eq!(1, child.own_depth());
+    assert_eq!(2, child.parent().len());
+  }
+
+  #[test]
+  fn child_copies_parent_bindings() {
+    let builtins = builtins();
+    let mut child = ScopeMap::with_parent(Arc::clone(&builtins));
+    child.define("x", 10);
+    child.push_layer();
+    child.define_parent("y", 11, 0);
+    *child.get_mut("print").unwrap() = 3;
+    child.define_parent("z", 12, 5);
+    assert!(Arc::ptr_eq(&builtins, child.parent()));
+    assert_eq!(Some(&1), builtins.get("print"));
+    assert!(!builtins.contains_key("z"));
+    assert_eq!(Some(&3), child.get("print"));
+    assert_eq!(Some(1), child.height_of("print"));
+    assert_eq!(Some(&1), child.get_parent("print", 2));
+    assert_eq!(Some(1), child.height_of("z"));
+    *child.get_mut("print").unwrap() += 1;
+    assert!(child.pop_layer());
+    assert_eq!(Some(&4), child.get("print"));
+    assert_eq!(Some(&12), child.get("z"));
+    let map = child.clone().into_map();
+    assert_eq!(2, map.depth());
+    assert_eq!(Some(&4), map.get("print"));
+    assert_eq!(Some(&1), map.get_parent("print", 1));
+    child.clear_all();
+    assert_eq!(1, child.own_depth());
+    assert!(!child.contains_key("x"));
+    assert_eq!(Some(&1), child.get("print"));
+  }
+
+  #[test]
+  fn child_own_layers_without_clone() {
+    #[derive(Debug, PartialEq)]
+    struct Value(i32);
+
+    let mut parent = ScopeMap::new();
+    parent.define("print", Value(1));
+    let mut child = ScopeMap::with_parent(Arc::new(parent));
+    child.define("x", Value(10));
+    child.push_layer();
+    child.define("y", Value(11));
+    assert_eq!(Some(Value(11)), child.remove("y"));
+    child.define("z", Value(12));
+    child.clear_top();
+    assert!(child.pop_layer());
+    assert!(!child.pop_layer());
+    assert_eq!(Some(&Value(10)), child.get("x"));
+    assert_eq!(Some(&Value(1)), child.get("print"));
+  }
+
+  #[test]
+  fn child_heights_below_base() {
+    let mut child = ScopeMap::with_parent(builtins());
+    child.push_layer();
+    child.define("x", 10);
+    assert_eq!(Some(0), child.height_of("print"));
+    assert_eq!(Some(2), child.height_of("x"));
+    assert_eq!(Some(2), child.depth_of("print"));
+    assert_eq!(Some(0), child.depth_of("x"));
+  }
+
+  #[test]
+  fn child_iter_merges() {
+    let mut child = ScopeMap::with_parent(builtins());
+    child.define("len", 3);
+    child.define("x", 4);
+    let mut pairs: Vec<_> = child.iter().map(|(key, value)| (*key, *value)).collect();
+    pairs.sort_unstable();
+    assert_eq!(vec![("len", 3), ("print", 1), ("x", 4)], pairs);
+  }
+
+  #[test]
+  fn child_shared_across_threads() {
+    let parent = builtins();
+    let handles: Vec<_> = (0..4).map(|i| {
+      let parent = Arc::clone(&parent);
+      thread::spawn(move || {
+        let mut child = ScopeMap::with_parent(parent);
+        child.define("id", i);
+        *child.get("print").unwrap() + *child.get("id").unwrap()
+      })
+    }).collect();
+    let results: Vec<i32> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+    assert_eq!(vec![1, 2, 3, 4], results);
+  }
+}
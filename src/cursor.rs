@@ -0,0 +1,170 @@
+use std::hash::BuildHasher;
+
+use crate::{map::Var, ScopeMap};
+
+/// A cursor over the bindings of a single key in a [`ScopeMap`], created by [`ScopeMap::stack_cursor`].
+///
+/// The cursor walks the key's bindings from the topmost layer down to the bottom.
+/// After the bottom binding, it points to a "ghost" position that does not refer to any binding;
+/// moving down from the ghost wraps around to the topmost binding, and moving up wraps around to the bottom one.
+pub struct StackCursor<'a, K, V> {
+  key: &'a K,
+  stack: &'a [Var<V>],
+  position: Option<usize>,
+}
+
+impl<'a, K, V> StackCursor<'a, K, V> {
+  #[inline]
+  pub(crate) fn new(key: &'a K, stack: &'a [Var<V>]) -> Self {
+    Self {
+      key,
+      stack,
+      position: stack.len().checked_sub(1),
+    }
+  }
+
+  /// Gets the key whose bindings the cursor walks.
+  #[inline]
+  pub fn key(&self) -> &'a K {
+    self.key
+  }
+
+  /// Gets the height and value of the binding under the cursor, or `None` if the cursor is at the ghost position.
+  #[inline]
+  pub fn current(&self) -> Option<(usize, &'a V)> {
+    let stack = self.stack;
+    self.position.map(|i| (stack[i].layer, &stack[i].value))
+  }
+
+  /// Gets the height and value of the binding directly below the cursor without moving it.
+  #[inline]
+  pub fn peek_down(&self) -> Option<(usize, &'a V)> {
+    let stack = self.stack;
+    let below = match self.position {
+      Some(i) => i.checked_sub(1)?,
+      None => stack.len().checked_sub(1)?,
+    };
+    Some((stack[below].layer, &stack[below].value))
+  }
+
+  /// Moves the cursor to the next binding down.
+  #[inline]
+  pub fn move_down(&mut self) {
+    self.position = step_down(self.position, self.stack.len());
+  }
+
+  /// Moves the cursor to the next binding up.
+  #[inline]
+  pub fn move_up(&mut self) {
+    self.position = step_up(self.position, self.stack.len());
+  }
+}
+
+impl<'a, K, V> Iterator for StackCursor<'a, K, V> {
+  type Item = (usize, &'a V);
+
+  /// Returns the binding under the cursor and moves the cursor down.
+  /// Returns `None` once the cursor reaches the ghost position.
+  #[inline]
+  fn next(&mut self) -> Option<Self::Item> {
+    let current = self.current()?;
+    self.move_down();
+    Some(current)
+  }
+}
+
+/// A cursor over the bindings of a single key in a [`ScopeMap`] that allows editing, created by [`ScopeMap::stack_cursor_mut`].
+///
+/// The cursor walks the key's bindings from the topmost layer down to the bottom,
+/// and follows the same ghost position rules as [`StackCursor`].
+pub struct StackCursorMut<'a, K, V, S: BuildHasher, const STACK: usize = 1, const LAYERS: usize = 1> {
+  map: &'a mut ScopeMap<K, V, S, STACK, LAYERS>,
+  stack_index: usize,
+  position: Option<usize>,
+}
+
+impl<'a, K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> StackCursorMut<'a, K, V, S, STACK, LAYERS> {
+  #[inline]
+  pub(crate) fn new(map: &'a mut ScopeMap<K, V, S, STACK, LAYERS>, stack_index: usize) -> Self {
+    let position = map.stack_at(stack_index).1.len().checked_sub(1);
+    Self {
+      map,
+      stack_index,
+      position,
+    }
+  }
+
+  /// Gets the key whose bindings the cursor walks.
+  #[inline]
+  pub fn key(&self) -> &K {
+    self.map.stack_at(self.stack_index).0
+  }
+
+  /// Gets the height and value of the binding under the cursor, or `None` if the cursor is at the ghost position.
+  #[inline]
+  pub fn current(&self) -> Option<(usize, &V)> {
+    let stack = self.map.stack_at(self.stack_index).1;
+    self.position.map(|i| (stack[i].layer, &stack[i].value))
+  }
+
+  /// Gets the height and a mutable reference to the value of the binding under the cursor,
+  /// or `None` if the cursor is at the ghost position.
+  #[inline]
+  pub fn current_mut(&mut self) -> Option<(usize, &mut V)> {
+    let stack = self.map.stack_at_mut(self.stack_index);
+    self.position.map(move |i| (stack[i].layer, &mut stack[i].value))
+  }
+
+  /// Moves the cursor to the next binding down.
+  #[inline]
+  pub fn move_down(&mut self) {
+    let len = self.map.stack_at(self.stack_index).1.len();
+    self.position = step_down(self.position, len);
+  }
+
+  /// Moves the cursor to the next binding up.
+  #[inline]
+  pub fn move_up(&mut self) {
+    let len = self.map.stack_at(self.stack_index).1.len();
+    self.position = step_up(self.position, len);
+  }
+
+  /// Replaces the value of the binding under the cursor and returns the previous value.
+  ///
+  /// Returns `None` and discards `value` if the cursor is at the ghost position.
+  #[inline]
+  pub fn replace(&mut self, value: V) -> Option<V> {
+    let i = self.position?;
+    Some(self.map.replace_in_stack(self.stack_index, i, value))
+  }
+
+  /// Removes the binding under the cursor from its layer and returns its height and value.
+  /// The cursor moves to the next binding down.
+  ///
+  /// Returns `None` if the cursor is at the ghost position.
+  #[inline]
+  pub fn remove_current(&mut self) -> Option<(usize, V)> {
+    let i = self.position?;
+    let var = self.map.remove_from_stack(self.stack_index, i);
+    self.position = i.checked_sub(1);
+    Some((var.layer, var.value))
+  }
+}
+
+#[inline]
+fn step_down(position: Option<usize>, len: usize) -> Option<usize> {
+  match position {
+    Some(i) => i.checked_sub(1),
+    None => len.checked_sub(1),
+  }
+}
+
+#[inline]
+fn step_up(position: Option<usize>, len: usize) -> Option<usize> {
+  match position {
+    Some(i) if i + 1 < len => Some(i + 1),
+    Some(_) => None,
+    None if len > 0 => Some(0),
+    None => None,
+  }
+}
@@ -0,0 +1,71 @@
+use std::{error::Error, fmt};
+
+/// Describes what happened when a key was defined with `try_define()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefineOutcome<V> {
+  /// The key was not visible before the definition.
+  New,
+  /// The new binding hides an existing binding in a lower layer.
+  Shadowed {
+    /// The height of the layer containing the hidden binding.
+    height: usize,
+  },
+  /// The key was already defined in the same layer, and its value was replaced.
+  /// Contains the previous value.
+  Replaced(V),
+}
+
+/// Describes a binding hidden by a binding in a higher layer.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ShadowInfo<'a, V> {
+  /// The height of the layer containing the hidden binding.
+  pub height: usize,
+  /// A reference to the value of the hidden binding.
+  pub value: &'a V,
+}
+
+impl<'a, V> Clone for ShadowInfo<'a, V> {
+  #[inline]
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<'a, V> Copy for ShadowInfo<'a, V> {}
+
+/// Controls how `try_define()` handles a key that is already defined in the target layer.
+///
+/// Only `try_define()` consults the policy. `define()` and the other defining methods always replace an existing binding in the target layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RedefinitionPolicy {
+  /// The new value replaces the existing one, and the previous value is reported as [`DefineOutcome::Replaced`].
+  ///
+  /// This is the default policy.
+  #[default]
+  Allow,
+  /// The definition is refused and the existing binding is left untouched.
+  Reject,
+  /// Behaves like `Allow`, for callers that treat the reported [`DefineOutcome::Replaced`] as a warning.
+  ReplaceAndWarn,
+}
+
+/// The error returned by `try_define()` when a redefinition is refused by [`RedefinitionPolicy::Reject`].
+///
+/// Hands back the key and value that were passed in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedefinitionError<K, V> {
+  /// The key that was already defined.
+  pub key: K,
+  /// The value that was not stored.
+  pub value: V,
+  /// The height of the layer that already contains the key.
+  pub height: usize,
+}
+
+impl<K, V> fmt::Display for RedefinitionError<K, V> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "key is already defined in layer {}", self.height)
+  }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> Error for RedefinitionError<K, V> {}
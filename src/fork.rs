@@ -0,0 +1,307 @@
+use std::{borrow::Borrow, hash::{BuildHasher, Hash}, sync::Arc};
+
+use crate::{overlay::Overlay, ScopeMap};
+
+/// A [`ScopeMap`] that shares its lower layers with other forks, created by [`ScopeMap::fork`].
+///
+/// The layers that existed when the map was forked are shared by reference between all of its forks.
+/// Layers pushed afterwards are the fork's own layers, so each fork can push and pop them independently.
+/// A new fork has no layers of its own, so it starts with the same depth as the map it was forked from.
+///
+/// Changes that reach into the shared layers (such as popping them, defining into them when the fork has no layers of its own,
+/// or getting mutable access to their values) are copy-on-write:
+/// the fork clones the shared layers the first time it modifies them while they are still shared.
+///
+/// [`ChildScopeMap`](crate::ChildScopeMap) follows the same conventions, but always keeps at least one layer of its own,
+/// and copies single bindings into it instead of cloning the shared layers.
+#[derive(Clone)]
+pub struct ForkedScopeMap<K, V, S: BuildHasher, const STACK: usize = 1, const LAYERS: usize = 1> {
+  inner: Overlay<K, V, S, STACK, LAYERS>,
+}
+
+impl<K, V, S: Clone + BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Creates a fork that shares all of the map's current layers.
+  ///
+  /// Unlike `clone()`, this does not copy any bindings.
+  /// The fork uses the map's hasher, redefinition policy and read tracking setting.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn fork(self: &Arc<Self>) -> ForkedScopeMap<K, V, S, STACK, LAYERS> {
+    ForkedScopeMap {
+      inner: Overlay::new(Arc::clone(self), 0),
+    }
+  }
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ForkedScopeMap<K, V, S, STACK, LAYERS> {
+  /// Gets the layers shared with other forks.
+  #[inline]
+  pub fn shared(&self) -> &Arc<ScopeMap<K, V, S, STACK, LAYERS>> {
+    self.inner.base()
+  }
+
+  /// Gets the number of layers in the map, including shared layers.
+  #[inline]
+  pub fn depth(&self) -> usize {
+    self.inner.depth()
+  }
+
+  /// Gets the number of layers that belong to this fork alone.
+  #[inline]
+  pub fn own_depth(&self) -> usize {
+    self.inner.own_depth()
+  }
+
+  /// Adds a new, empty layer owned by this fork.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn push_layer(&mut self) {
+    self.inner.push_layer()
+  }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone, S: Clone + BuildHasher, const STACK: usize, const LAYERS: usize> ForkedScopeMap<K, V, S, STACK, LAYERS> {
+  /// Creates another fork that shares the same layers as this one.
+  ///
+  /// Layers owned by this fork are copied into the new fork rather than shared.
+  #[inline]
+  pub fn fork(&self) -> Self {
+    self.clone()
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys/values.
+  /// Returns `true` if a layer was removed.
+  ///
+  /// Popping a shared layer clones the shared layers if they are shared with other forks.
+  #[inline]
+  pub fn pop_layer(&mut self) -> bool {
+    self.inner.pop_layer()
+  }
+
+  /// Adds the specified entry to the topmost layer.
+  ///
+  /// If the fork has no layers of its own, the shared layers are cloned first if they are shared with other forks.
+  #[inline]
+  pub fn define(&mut self, key: K, value: V) {
+    self.inner.define(key, value)
+  }
+
+  /// Adds the specified entry in the layer `min_depth` layers from the top. Saturates to base layer.
+  ///
+  /// If the layer is shared, the shared layers are cloned first if they are shared with other forks.
+  #[inline]
+  pub fn define_parent(&mut self, key: K, value: V, min_depth: usize) {
+    self.inner.define_parent(key, value, min_depth)
+  }
+
+  /// Removes the entry with the specified key from the topmost layer and returns its value.
+  #[inline]
+  pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.remove(key)
+  }
+
+  /// Removes all entries in the topmost layer.
+  #[inline]
+  pub fn clear_top(&mut self) {
+    self.inner.clear_top()
+  }
+
+  /// Gets a mutable reference to the topmost value associated with a key.
+  ///
+  /// If the binding is in a shared layer, the shared layers are cloned first if they are shared with other forks.
+  #[inline]
+  pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.get_mut(key)
+  }
+
+  /// Converts the fork into a standalone `ScopeMap` with the same layers and bindings.
+  ///
+  /// The shared layers are cloned if they are still shared with other forks.
+  #[inline]
+  pub fn into_map(self) -> ScopeMap<K, V, S, STACK, LAYERS> {
+    self.inner.into_map()
+  }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ForkedScopeMap<K, V, S, STACK, LAYERS> {
+  /// Gets the number of unique keys in the map.
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.inner.len()
+  }
+
+  /// Returns `true` if the map is empty.
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Returns `true` if the map contains the specified key in any layer.
+  #[inline]
+  pub fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.contains_key(key)
+  }
+
+  /// Returns `true` if the map contains the specified key at the top layer.
+  #[inline]
+  pub fn contains_key_at_top<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.contains_key_at_top(key)
+  }
+
+  /// Gets a reference to the topmost value associated with a key.
+  #[inline]
+  pub fn get<Q>(&self, key: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.get(key)
+  }
+
+  /// Gets a reference to the value associated with a key at least `min_depth` layers below the topmost layer.
+  #[inline]
+  pub fn get_parent<Q>(&self, key: &Q, min_depth: usize) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.get_parent(key, min_depth)
+  }
+
+  /// Gets the height of the specified key (i.e. how many layers up from the bottom that the key last appears).
+  #[inline]
+  pub fn height_of<Q>(&self, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.height_of(key)
+  }
+
+  /// Gets the depth of the specified key (i.e. how many layers down from the top that the key first appears).
+  #[inline]
+  pub fn depth_of<Q>(&self, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.inner.depth_of(key)
+  }
+
+  /// Iterates over all key-value pairs in arbitrary order.
+  ///
+  /// The iterator element type is `(&'a K, &'a V)`.
+  #[inline]
+  pub fn iter(&self) -> impl Iterator<Item = (&'_ K, &'_ V)> {
+    self.inner.iter()
+  }
+
+  /// Iterates over all keys in arbitrary order.
+  ///
+  /// The iterator element type is `&'a K`.
+  #[inline]
+  pub fn keys(&self) -> impl Iterator<Item = &'_ K> {
+    self.iter().map(|(key, _)| key)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn globals() -> Arc<ScopeMap<&'static str, i32>> {
+    let mut map = ScopeMap::new();
+    map.define("print", 1);
+    map.push_layer();
+    map.define("module", 2);
+    Arc::new(map)
+  }
+
+  #[test]
+  fn fork_shares_layers() {
+    let globals = globals();
+    let mut a = globals.fork();
+    let mut b = globals.fork();
+    a.push_layer();
+    a.define("x", 10);
+    b.push_layer();
+    b.define("x", 20);
+    b.define("module", 21);
+    assert_eq!(3, a.depth());
+    assert_eq!(Some(&10), a.get("x"));
+    assert_eq!(Some(&20), b.get("x"));
+    assert_eq!(Some(&2), a.get("module"));
+    assert_eq!(Some(&21), b.get("module"));
+    assert_eq!(Some(&2), b.get_parent("module", 1));
+    assert_eq!(Some(2), b.height_of("module"));
+    assert_eq!(Some(2), b.depth_of("print"));
+    assert_eq!(3, b.len());
+    assert_eq!(3, Arc::strong_count(&globals));
+    assert!(a.pop_layer());
+    assert!(!a.contains_key("x"));
+    assert!(Arc::ptr_eq(&globals, a.shared()));
+  }
+
+  #[test]
+  fn fork_copy_on_write() {
+    let globals = globals();
+    let mut fork = globals.fork();
+    fork.define("module", 3);
+    *fork.get_mut("print").unwrap() = 4;
+    assert!(!Arc::ptr_eq(&globals, fork.shared()));
+    assert_eq!(Some(&2), globals.get("module"));
+    assert_eq!(Some(&1), globals.get("print"));
+    assert_eq!(Some(&3), fork.get("module"));
+    assert!(fork.pop_layer());
+    assert_eq!(2, globals.depth());
+    assert_eq!(1, fork.depth());
+  }
+
+  #[test]
+  fn fork_no_op_keeps_sharing() {
+    let mut base = ScopeMap::new();
+    base.define("print", 1);
+    let base = Arc::new(base);
+    let mut fork = base.fork();
+    assert!(!fork.pop_layer());
+    assert!(Arc::ptr_eq(&base, fork.shared()));
+
+    let mut base = (*globals()).clone();
+    base.push_layer();
+    let base = Arc::new(base);
+    let mut fork = base.fork();
+    fork.clear_top();
+    assert!(Arc::ptr_eq(&base, fork.shared()));
+  }
+
+  #[test]
+  fn fork_into_map() {
+    let mut fork = globals().fork();
+    fork.push_layer();
+    fork.define("x", 10);
+    fork.define_parent("module", 5, 1);
+    let map = fork.into_map();
+    assert_eq!(3, map.depth());
+    assert_eq!(Some((&10, 2)), map.get_parent_height("x", 0));
+    assert_eq!(Some(&5), map.get("module"));
+    assert_eq!(Some(&1), map.get("print"));
+  }
+}
@@ -0,0 +1,93 @@
+use std::{hash::BuildHasher, ops::{Deref, DerefMut}};
+
+use crate::{ScopeMap, ScopeSet};
+
+/// A guard that adds a layer to a [`ScopeMap`] and removes it when dropped.
+///
+/// Created by [`ScopeMap::scope`]. The guard dereferences to the map, so it can be used in its place.
+/// When dropped, it removes every layer above the depth the map had before the guard was created,
+/// including any layers that were pushed through the guard and not popped.
+pub struct ScopeGuard<'a, K, V, S: BuildHasher, const STACK: usize = 1, const LAYERS: usize = 1> {
+  map: &'a mut ScopeMap<K, V, S, STACK, LAYERS>,
+  base_depth: usize,
+}
+
+impl<'a, K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeGuard<'a, K, V, S, STACK, LAYERS> {
+  #[inline]
+  pub(crate) fn new(map: &'a mut ScopeMap<K, V, S, STACK, LAYERS>) -> Self {
+    let base_depth = map.depth();
+    map.push_layer();
+    Self {
+      map,
+      base_depth,
+    }
+  }
+}
+
+impl<'a, K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> Deref for ScopeGuard<'a, K, V, S, STACK, LAYERS> {
+  type Target = ScopeMap<K, V, S, STACK, LAYERS>;
+
+  #[inline]
+  fn deref(&self) -> &Self::Target {
+    self.map
+  }
+}
+
+impl<'a, K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> DerefMut for ScopeGuard<'a, K, V, S, STACK, LAYERS> {
+  #[inline]
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    self.map
+  }
+}
+
+impl<'a, K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> Drop for ScopeGuard<'a, K, V, S, STACK, LAYERS> {
+  #[inline]
+  fn drop(&mut self) {
+    while self.map.depth() > self.base_depth && self.map.pop_layer() {}
+  }
+}
+
+/// A guard that adds a layer to a [`ScopeSet`] and removes it when dropped.
+///
+/// Created by [`ScopeSet::scope`]. The guard dereferences to the set, so it can be used in its place.
+/// When dropped, it removes every layer above the depth the set had before the guard was created,
+/// including any layers that were pushed through the guard and not popped.
+pub struct ScopeSetGuard<'a, T, S: BuildHasher, const STACK: usize = 1, const LAYERS: usize = 1> {
+  set: &'a mut ScopeSet<T, S, STACK, LAYERS>,
+  base_depth: usize,
+}
+
+impl<'a, T, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeSetGuard<'a, T, S, STACK, LAYERS> {
+  #[inline]
+  pub(crate) fn new(set: &'a mut ScopeSet<T, S, STACK, LAYERS>) -> Self {
+    let base_depth = set.depth();
+    set.push_layer();
+    Self {
+      set,
+      base_depth,
+    }
+  }
+}
+
+impl<'a, T, S: BuildHasher, const STACK: usize, const LAYERS: usize> Deref for ScopeSetGuard<'a, T, S, STACK, LAYERS> {
+  type Target = ScopeSet<T, S, STACK, LAYERS>;
+
+  #[inline]
+  fn deref(&self) -> &Self::Target {
+    self.set
+  }
+}
+
+impl<'a, T, S: BuildHasher, const STACK: usize, const LAYERS: usize> DerefMut for ScopeSetGuard<'a, T, S, STACK, LAYERS> {
+  #[inline]
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    self.set
+  }
+}
+
+impl<'a, T, S: BuildHasher, const STACK: usize, const LAYERS: usize> Drop for ScopeSetGuard<'a, T, S, STACK, LAYERS> {
+  #[inline]
+  fn drop(&mut self) {
+    while self.set.depth() > self.base_depth && self.set.pop_layer() {}
+  }
+}
@@ -0,0 +1,51 @@
+use crate::LayerId;
+
+/// Refers to a specific binding in a [`ScopeMap`](crate::ScopeMap), allowing repeated access without hashing the key.
+///
+/// A handle is tied to the binding it was created for.
+/// Once the binding's layer is popped or the binding is removed, the handle no longer resolves,
+/// even if the key is later defined again at the same height.
+/// Redefining the key in the same layer while the binding exists replaces the binding's value but keeps the handle valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BindingHandle {
+  index: usize,
+  position: usize,
+  layer: LayerId,
+  stamp: usize,
+}
+
+impl BindingHandle {
+  #[inline]
+  pub(crate) fn new(index: usize, position: usize, layer: LayerId, stamp: usize) -> Self {
+    Self {
+      index,
+      position,
+      layer,
+      stamp,
+    }
+  }
+
+  /// Gets the map index of the binding's key.
+  #[inline]
+  pub(crate) fn index(&self) -> usize {
+    self.index
+  }
+
+  /// Gets the position of the binding in its key's value stack at the time the handle was created.
+  #[inline]
+  pub(crate) fn position(&self) -> usize {
+    self.position
+  }
+
+  /// Gets the stamp that distinguishes the binding from later bindings at the same height.
+  #[inline]
+  pub(crate) fn stamp(&self) -> usize {
+    self.stamp
+  }
+
+  /// Gets the ID of the layer containing the binding.
+  #[inline]
+  pub fn layer(&self) -> LayerId {
+    self.layer
+  }
+}
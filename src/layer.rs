@@ -0,0 +1,62 @@
+use std::{error::Error, fmt};
+
+/// Identifies a specific layer of a [`ScopeMap`](crate::ScopeMap) or [`ScopeSet`](crate::ScopeSet).
+///
+/// A `LayerId` stays tied to the layer it was created for: once that layer is popped,
+/// the ID no longer matches any layer, even if another layer is later pushed at the same height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LayerId {
+  height: usize,
+  generation: usize,
+}
+
+impl LayerId {
+  #[inline]
+  pub(crate) fn new(height: usize, generation: usize) -> Self {
+    Self {
+      height,
+      generation,
+    }
+  }
+
+  /// Gets the height of the layer. A height of 0 refers to the bottom layer.
+  #[inline]
+  pub fn height(&self) -> usize {
+    self.height
+  }
+
+  /// Gets the generation of the layer, which distinguishes it from other layers pushed at the same height.
+  #[inline]
+  pub fn generation(&self) -> usize {
+    self.generation
+  }
+}
+
+/// The error returned by `pop_layer_checked()` when the layer cannot be popped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopLayerError {
+  /// The topmost layer is not the expected layer.
+  Mismatch {
+    /// The layer that was expected to be on top.
+    expected: LayerId,
+    /// The layer that is actually on top.
+    top: LayerId,
+  },
+  /// The expected layer is the base layer, which cannot be popped.
+  BaseLayer,
+}
+
+impl fmt::Display for PopLayerError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PopLayerError::Mismatch { expected, top } => write!(
+        f,
+        "expected layer {} (generation {}) on top, found layer {} (generation {})",
+        expected.height, expected.generation, top.height, top.generation
+      ),
+      PopLayerError::BaseLayer => write!(f, "the base layer cannot be popped"),
+    }
+  }
+}
+
+impl Error for PopLayerError {}
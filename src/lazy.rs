@@ -0,0 +1,199 @@
+use std::{
+  borrow::Borrow,
+  cell::{Cell, OnceCell},
+  fmt,
+  hash::{BuildHasher, Hash},
+  ops::{Deref, DerefMut},
+};
+
+use crate::ScopeMap;
+
+/// A value that is computed the first time it is forced, for use as the value type of a [`ScopeMap`].
+///
+/// A `ScopeMap<K, LazyValue<V>>` can hold both eager and lazy bindings; lazy ones are added with [`ScopeMap::define_lazy`].
+/// Lookups such as `get()` return the `LazyValue` itself; the thunk runs the first time the value is dereferenced or forced,
+/// and its result replaces the thunk in place, so it runs at most once.
+///
+/// `F` is the type of the thunk. It defaults to a boxed closure, so that bindings with different thunks can share a map.
+/// The thunk does not need to be `Send`; like other single-threaded cells, a `LazyValue` is not `Sync`.
+///
+/// # Panics
+///
+/// If the thunk panics, the value is left unforced and any further attempt to force it panics as well.
+pub struct LazyValue<V, F = Box<dyn FnOnce() -> V>> {
+  value: OnceCell<V>,
+  thunk: Cell<Option<F>>,
+}
+
+impl<V, F: FnOnce() -> V> LazyValue<V, F> {
+  /// Creates a value that is computed by `thunk` when first forced.
+  #[inline]
+  pub fn new(thunk: F) -> Self {
+    Self {
+      value: OnceCell::new(),
+      thunk: Cell::new(Some(thunk)),
+    }
+  }
+
+  /// Creates a value that has already been computed.
+  #[inline]
+  pub fn ready(value: V) -> Self {
+    Self {
+      value: OnceCell::from(value),
+      thunk: Cell::new(None),
+    }
+  }
+
+  /// Returns `true` if the value has been computed.
+  #[inline]
+  pub fn is_forced(this: &Self) -> bool {
+    this.value.get().is_some()
+  }
+
+  /// Computes the value if it hasn't been computed yet, and returns a reference to it.
+  #[inline]
+  pub fn force(this: &Self) -> &V {
+    this.value.get_or_init(|| {
+      let thunk = this.thunk.take();
+      thunk.expect("lazy value was poisoned by a panicking thunk")()
+    })
+  }
+
+  /// Computes the value if it hasn't been computed yet, and returns a mutable reference to it.
+  #[inline]
+  pub fn force_mut(this: &mut Self) -> &mut V {
+    Self::force(this);
+    this.value.get_mut().unwrap()
+  }
+
+  /// Computes the value if it hasn't been computed yet, and returns it.
+  #[inline]
+  pub fn into_inner(this: Self) -> V {
+    Self::force(&this);
+    this.value.into_inner().unwrap()
+  }
+}
+
+impl<V, F: FnOnce() -> V> From<V> for LazyValue<V, F> {
+  #[inline]
+  fn from(value: V) -> Self {
+    Self::ready(value)
+  }
+}
+
+impl<V, F: FnOnce() -> V> Deref for LazyValue<V, F> {
+  type Target = V;
+
+  /// Computes the value if it hasn't been computed yet, and returns a reference to it.
+  #[inline]
+  fn deref(&self) -> &V {
+    Self::force(self)
+  }
+}
+
+impl<V, F: FnOnce() -> V> DerefMut for LazyValue<V, F> {
+  /// Computes the value if it hasn't been computed yet, and returns a mutable reference to it.
+  #[inline]
+  fn deref_mut(&mut self) -> &mut V {
+    Self::force_mut(self)
+  }
+}
+
+impl<V: fmt::Debug, F> fmt::Debug for LazyValue<V, F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.value.get() {
+      Some(value) => f.debug_tuple("LazyValue").field(value).finish(),
+      None => f.write_str("LazyValue(<unforced>)"),
+    }
+  }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, LazyValue<V>, S, STACK, LAYERS> {
+  /// Adds a binding to the topmost layer whose value is computed by `thunk` the first time it is forced.
+  #[inline]
+  pub fn define_lazy(&mut self, key: K, thunk: impl FnOnce() -> V + 'static) {
+    self.define(key, LazyValue::new(Box::new(thunk)))
+  }
+}
+
+impl<K: Eq + Hash, V, F: FnOnce() -> V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, LazyValue<V, F>, S, STACK, LAYERS> {
+  /// Returns whether the topmost binding of a key has been computed, or `None` if the key does not exist.
+  ///
+  /// This neither forces the value nor counts as a read of the binding.
+  #[inline]
+  pub fn is_forced<Q>(&self, key: &Q) -> Option<bool>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.peek_equivalent(key).map(LazyValue::is_forced)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::rc::Rc;
+
+  #[test]
+  fn lazy_forced_once() {
+    let runs = Rc::new(Cell::new(0));
+    let mut map = ScopeMap::new();
+    let counter = Rc::clone(&runs);
+    map.define_lazy("table", move || {
+      counter.set(counter.get() + 1);
+      vec![1, 2, 3]
+    });
+    map.define("eager", LazyValue::ready(vec![4]));
+    assert_eq!(Some(false), map.is_forced("table"));
+    assert_eq!(Some(true), map.is_forced("eager"));
+    assert_eq!(None, map.is_forced("missing"));
+    assert!(map.get("table").is_some());
+    assert_eq!(0, runs.get());
+    assert_eq!(vec![1, 2, 3], **map.get("table").unwrap());
+    assert_eq!(Some(3), map.get("table").map(|table| table.len()));
+    assert_eq!(Some(true), map.is_forced("table"));
+    assert_eq!(1, runs.get());
+  }
+
+  #[test]
+  fn lazy_forced_by_deref() {
+    let mut map = ScopeMap::new();
+    map.define_lazy("x", || 1);
+    map.push_layer();
+    map.define_lazy("x", || 2);
+    assert_eq!(1, **map.get_parent("x", 1).unwrap());
+    assert_eq!(Some(false), map.is_forced("x"));
+    **map.get_mut("x").unwrap() += 10;
+    assert_eq!(12, **map.get("x").unwrap());
+    map.pop_layer();
+    assert_eq!(1, LazyValue::into_inner(map.remove("x").unwrap()));
+  }
+
+  #[test]
+  fn lazy_is_forced_does_not_read() {
+    let mut map = ScopeMap::new();
+    map.set_read_tracking(true);
+    map.push_layer();
+    map.define_lazy("x", || 1);
+    map.define_lazy("y", || 2);
+    assert_eq!(Some(false), map.is_forced("x"));
+    assert_eq!(2, **map.get("y").unwrap());
+    assert_eq!(Some(vec![&"x"]), map.pop_layer_reporting());
+  }
+
+  #[test]
+  fn lazy_unboxed_thunk() {
+    let mut map = ScopeMap::new();
+    map.define("x", LazyValue::new((|| 7) as fn() -> i32));
+    assert_eq!(7, **map.get("x").unwrap());
+  }
+
+  #[test]
+  fn lazy_debug() {
+    let value = LazyValue::new(|| 5);
+    assert_eq!("LazyValue(<unforced>)", format!("{:?}", value));
+    LazyValue::force(&value);
+    assert_eq!("LazyValue(5)", format!("{:?}", value));
+  }
+}
@@ -40,9 +40,43 @@
 //! [`ScopeMap`]: map/struct.ScopeMap.html
 //! [`ScopeSet`]: set/struct.ScopeSet.html
 
+mod address;
+mod algebra;
+mod child;
+mod cursor;
+mod define;
+mod fork;
+mod guard;
+mod handle;
+mod layer;
+mod lazy;
 mod map;
+mod namespaced;
+mod observer;
+mod overlay;
+pub mod reference;
+mod render;
 mod set;
+mod stats;
+mod trace;
+mod tree;
 
+pub use address::*;
+pub use algebra::*;
+pub use child::*;
+pub use cursor::*;
+pub use define::*;
+pub use fork::*;
+pub use guard::*;
+pub use handle::*;
+pub use layer::*;
+pub use lazy::*;
 pub use map::*;
+pub use namespaced::*;
+pub use observer::*;
+pub use render::*;
 pub use set::*;
+pub use stats::*;
+pub use trace::*;
+pub use tree::*;
 
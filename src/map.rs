@@ -1,14 +1,42 @@
 use std::{
+  any::Any,
   borrow::Borrow,
-  collections::{hash_map::RandomState, HashSet},
+  collections::{hash_map::RandomState, HashMap, HashSet},
+  error::Error,
+  fmt,
   hash::{Hash, BuildHasher},
-  ops::Index
+  mem,
+  ops::Index,
+  sync::{Arc, Mutex, PoisonError},
 };
 
-use indexmap::{IndexMap};
+use indexmap::{map::MutableKeys, Equivalent, IndexMap};
 use smallvec::{smallvec, SmallVec};
 
-type ScopeMapValueStack<V> = SmallVec<[V; 1]>;
+use crate::{Address, BindingHandle, DefineOutcome, LayerId, MemoryStats, ObservedScopeMap, PopLayerError, RedefinitionError, RedefinitionPolicy, ScopeGuard, ShadowInfo, StackCursor, StackCursorMut};
+
+type ScopeMapValueStack<V, const N: usize> = SmallVec<[V; N]>;
+
+type LayerMeta = Arc<dyn Any + Send + Sync>;
+
+/// Finds the position of the binding defined at `height` in a value stack.
+///
+/// Value stacks are sorted by layer height, so this is a binary search.
+#[inline]
+pub(crate) fn find_in_stack<T>(stack: &[Var<T>], height: usize) -> Option<usize> {
+  stack.binary_search_by_key(&height, |var| var.layer).ok()
+}
+
+/// Gets the number of bindings in a value stack that are at least `min_depth` layers below the top of a map with `depth` layers.
+///
+/// These bindings are always at the bottom of the stack, so they can be found with a binary search.
+#[inline]
+pub(crate) fn parent_count<T>(stack: &[Var<T>], depth: usize, min_depth: usize) -> usize {
+  match (depth - 1).checked_sub(min_depth) {
+    Some(max_height) => stack.partition_point(|var| var.layer <= max_height),
+    None => 0,
+  }
+}
 
 #[inline(always)]
 fn invert_index(index: usize, n: usize) -> usize {
@@ -20,40 +48,331 @@ fn invert_index(index: usize, n: usize) -> usize {
 }
 
 #[derive(Clone)]
-struct Var<T> {
-  value: T,
-  layer: usize,
+pub(crate) struct Var<T> {
+  pub(crate) value: T,
+  pub(crate) layer: usize,
+  /// Identifies this binding among all bindings the map has ever held, so that handles to removed bindings stop resolving.
+  ///
+  /// Stamps are drawn from the same counter as layer generations; redefining a key in the same layer keeps the stamp.
+  pub(crate) stamp: usize,
+}
+
+impl<T> Var<T> {
+  #[inline]
+  pub(crate) fn new(value: T, layer: usize, stamp: usize) -> Self {
+    Self {
+      value,
+      layer,
+      stamp,
+    }
+  }
+}
+
+/// Takes the next value from a map's generation counter.
+#[inline(always)]
+fn next_stamp(next_generation: &mut usize) -> usize {
+  let stamp = *next_generation;
+  *next_generation += 1;
+  stamp
+}
+
+/// The keys defined in one layer of a map, along with the slot assigned to each.
+///
+/// Removing a binding leaves a vacant slot instead of moving the bindings after it.
+/// Vacant slots are reused by later definitions, and all slots are released when the layer is cleared.
+#[derive(Clone, Default)]
+struct Layer {
+  /// Maps the map index of each key defined in the layer to its slot.
+  slots: HashMap<usize, usize>,
+  /// Maps each slot to the map index of the key defined in it, or `None` if its binding was removed.
+  entries: Vec<Option<usize>>,
+  /// The vacant slots, with the next one to be reused last.
+  free: Vec<usize>,
+}
+
+impl Layer {
+  /// Adds a key to the layer, returning `false` if it is already there.
+  ///
+  /// The key takes the most recently vacated slot, or a new slot if there are none.
+  #[inline]
+  fn insert(&mut self, index: usize) -> bool {
+    match self.slots.entry(index) {
+      std::collections::hash_map::Entry::Occupied(..) => false,
+      std::collections::hash_map::Entry::Vacant(entry) => {
+        match self.free.pop() {
+          Some(slot) => {
+            entry.insert(slot);
+            self.entries[slot] = Some(index);
+          },
+          None => {
+            entry.insert(self.entries.len());
+            self.entries.push(Some(index));
+          },
+        }
+        true
+      },
+    }
+  }
+
+  /// Removes a key from the layer, leaving its slot vacant. Returns `false` if the key was not there.
+  #[inline]
+  fn remove(&mut self, index: usize) -> bool {
+    match self.slots.remove(&index) {
+      Some(slot) => {
+        self.entries[slot] = None;
+        self.free.push(slot);
+        true
+      },
+      None => false,
+    }
+  }
+
+  #[inline]
+  fn contains(&self, index: usize) -> bool {
+    self.slots.contains_key(&index)
+  }
+
+  /// Gets the number of keys in the layer.
+  #[inline]
+  fn len(&self) -> usize {
+    self.slots.len()
+  }
+
+  /// Gets the number of slots in the layer, including vacant ones.
+  #[inline]
+  fn slot_count(&self) -> usize {
+    self.entries.len()
+  }
+
+  #[inline]
+  fn slot_of(&self, index: usize) -> Option<usize> {
+    self.slots.get(&index).copied()
+  }
+
+  #[inline]
+  fn index_at(&self, slot: usize) -> Option<usize> {
+    self.entries.get(slot).copied().flatten()
+  }
+
+  /// Iterates over the map indices of the keys in the layer, in slot order.
+  #[inline]
+  fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+    self.entries.iter().filter_map(|entry| *entry)
+  }
+
+  /// Removes every key from the layer, releasing all of its slots, and iterates over their map indices in slot order.
+  #[inline]
+  fn vacate(&mut self) -> impl Iterator<Item = usize> + '_ {
+    self.slots.clear();
+    self.free.clear();
+    self.entries.drain(..).flatten()
+  }
+
+  #[inline]
+  fn into_indices(self) -> impl Iterator<Item = usize> {
+    self.entries.into_iter().flatten()
+  }
+
+  #[inline]
+  fn capacity(&self) -> usize {
+    self.slots.capacity()
+  }
+
+  #[inline]
+  fn reserve(&mut self, additional: usize) {
+    self.slots.reserve(additional);
+    self.entries.reserve(additional);
+  }
+
+  #[inline]
+  fn shrink_to_fit(&mut self) {
+    self.slots.shrink_to_fit();
+    self.entries.shrink_to_fit();
+    self.free.shrink_to_fit();
+  }
+
+  /// Moves a key to a new map index, keeping its slot.
+  #[inline]
+  fn reindex(&mut self, from: usize, to: usize) {
+    if let Some(slot) = self.slots.remove(&from) {
+      self.slots.insert(to, slot);
+      self.entries[slot] = Some(to);
+    }
+  }
+
+  /// Replaces the map index of every key in the layer with its new index in `new_indices`, keeping slots in place.
+  fn remap(&mut self, new_indices: &[Option<usize>]) {
+    self.slots.clear();
+    for (slot, entry) in self.entries.iter_mut().enumerate() {
+      if let Some(index) = entry {
+        *entry = new_indices[*index];
+        match entry {
+          Some(index) => { self.slots.insert(*index, slot); },
+          None => self.free.push(slot),
+        }
+      }
+    }
+  }
+}
+
+/// Records which bindings have been resolved by a lookup while read tracking is enabled.
+///
+/// Bindings are identified by their map index and layer height.
+/// The table is only allocated while tracking is enabled, so maps that don't track reads pay for a single pointer.
+#[derive(Default)]
+struct ReadMarks(Option<Box<MarkTable>>);
+
+type MarkTable = Mutex<HashSet<(usize, usize)>>;
+
+impl ReadMarks {
+  #[inline]
+  fn is_enabled(&self) -> bool {
+    self.0.is_some()
+  }
+
+  #[inline]
+  fn set_enabled(&mut self, enabled: bool) {
+    if enabled != self.is_enabled() {
+      self.0 = enabled.then(Default::default);
+    }
+  }
+
+  #[inline(always)]
+  fn mark(&self, index: usize, height: usize) {
+    if let Some(marks) = &self.0 {
+      marks.lock().unwrap_or_else(PoisonError::into_inner).insert((index, height));
+    }
+  }
+
+  #[inline(always)]
+  fn mark_mut(&mut self, index: usize, height: usize) {
+    if let Some(marks) = &mut self.0 {
+      marks.get_mut().unwrap_or_else(PoisonError::into_inner).insert((index, height));
+    }
+  }
+
+  #[inline]
+  fn is_marked(&self, index: usize, height: usize) -> bool {
+    match &self.0 {
+      Some(marks) => marks.lock().unwrap_or_else(PoisonError::into_inner).contains(&(index, height)),
+      None => false,
+    }
+  }
+
+  /// Forgets the mark of a binding that has been removed or replaced.
+  #[inline(always)]
+  fn unmark(&mut self, index: usize, height: usize) {
+    if let Some(marks) = &mut self.0 {
+      marks.get_mut().unwrap_or_else(PoisonError::into_inner).remove(&(index, height));
+    }
+  }
+
+  #[inline]
+  fn clear(&mut self) {
+    if let Some(marks) = &mut self.0 {
+      marks.get_mut().unwrap_or_else(PoisonError::into_inner).clear();
+    }
+  }
+
+  /// Moves the mark of a binding to a new map index.
+  #[inline]
+  fn reindex(&mut self, from: usize, to: usize, height: usize) {
+    if let Some(marks) = &mut self.0 {
+      let marks = marks.get_mut().unwrap_or_else(PoisonError::into_inner);
+      if marks.remove(&(from, height)) {
+        marks.insert((to, height));
+      }
+    }
+  }
+
+  /// Replaces the map index of every mark with its new index in `new_indices`, dropping marks of removed keys.
+  fn remap(&mut self, new_indices: &[Option<usize>]) {
+    if let Some(marks) = &mut self.0 {
+      let marks = marks.get_mut().unwrap_or_else(PoisonError::into_inner);
+      *marks = marks.drain().filter_map(|(index, height)| Some((new_indices[index]?, height))).collect();
+    }
+  }
+}
+
+impl Clone for ReadMarks {
+  fn clone(&self) -> Self {
+    Self(self.0.as_ref().map(|marks| Box::new(Mutex::new(marks.lock().unwrap_or_else(PoisonError::into_inner).clone()))))
+  }
+}
+
+/// The error returned by [`ScopeMap::check_invariants`] when the map's internal state is inconsistent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantError(String);
+
+impl fmt::Display for InvariantError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "scope map invariant violated: {}", self.0)
+  }
 }
 
+impl Error for InvariantError {}
+
 /// A layered hash map for representing scoped variables and their values.
+///
+/// `STACK` sets how many bindings of each key are stored inline before its value stack spills to the heap,
+/// and `LAYERS` sets how many layers are stored inline before the layer list spills to the heap.
+/// Raising them can help workloads with heavy shadowing or consistently deep scopes.
+/// Maps with non-default inline capacities are created with `Default::default()` or the `configured_*` constructors,
+/// and support the same APIs as maps with the default configuration.
+///
+/// ```rust
+/// # use quickscope::ScopeMap;
+/// # use std::collections::hash_map::RandomState;
+/// let mut map: ScopeMap<&str, i32, RandomState, 4, 8> = ScopeMap::configured_with_capacity(16);
+/// map.define("a", 1);
+/// map.with_layer(|map| {
+///   map.define("a", 2);
+///   assert_eq!(Some((&1, 0)), map.get_parent_height("a", 1));
+/// });
+/// ```
 #[derive(Clone)]
-pub struct ScopeMap<K, V, S: BuildHasher = RandomState> {
+pub struct ScopeMap<K, V, S: BuildHasher = RandomState, const STACK: usize = 1, const LAYERS: usize = 1> {
   /// Stores a value stack for each variable.
   ///
   /// The bottom of a variable's stack corresponds to the lowest layer on which the variable appears.
-  map: IndexMap<K, ScopeMapValueStack<Var<V>>, S>,
+  map: IndexMap<K, ScopeMapValueStack<Var<V>, STACK>, S>,
   /// Stores the layers of the stack.
   ///
-  /// Each layer contains map indices indicating which variables are created or updated in that layer.
-  layers: SmallVec<[HashSet<usize>; 1]>,
+  /// Each layer contains map indices indicating which variables are created or updated in that layer,
+  /// along with the slot each variable was assigned when it was first defined there.
+  layers: SmallVec<[Layer; LAYERS]>,
+  /// Stores the generation of each layer, parallel to `layers`.
+  ///
+  /// Used to tell apart layers that were pushed at the same height.
+  generations: SmallVec<[usize; LAYERS]>,
+  /// The generation to assign to the next pushed layer, or the stamp to assign to the next new binding.
+  next_generation: usize,
+  /// Stores the metadata attached to layers, as pairs of layer height and metadata sorted by height.
+  ///
+  /// Only layers with metadata have an entry, so maps that don't use metadata never allocate here.
+  layer_meta: Vec<(usize, LayerMeta)>,
   /// The number of currently empty variable stacks.
   ///
   /// Used internally to accurately calculate the number of active variables.
   empty_key_count: usize,
+  /// Determines how `try_define()` handles keys that already exist in the top layer.
+  policy: RedefinitionPolicy,
+  /// Records the bindings resolved by lookups while read tracking is enabled.
+  reads: ReadMarks,
 }
 
-impl<K, V, S: Default + BuildHasher> Default for ScopeMap<K, V, S> {
+impl<K, V, S: Default + BuildHasher, const STACK: usize, const LAYERS: usize> Default for ScopeMap<K, V, S, STACK, LAYERS> {
   /// Creates a new `ScopeMap` with the default configuration.
   #[inline]
   fn default() -> Self {
-    Self::with_hasher(Default::default())
+    Self::from_index_map(Default::default())
   }
 }
 
-impl<K, Q: ?Sized, V, S> Index<&Q> for ScopeMap<K, V, S>
+impl<K, Q, V, S, const STACK: usize, const LAYERS: usize> Index<&Q> for ScopeMap<K, V, S, STACK, LAYERS>
 where 
   K: Eq + Hash + Borrow<Q>,
-  Q: Eq + Hash,
+  Q: ?Sized + Eq + Hash,
   S: BuildHasher,
 {
   type Output = V;
@@ -74,11 +393,7 @@ impl<K, V> ScopeMap<K, V, RandomState> {
   /// Creates an empty `ScopeMap` with a default hasher and capacity.
   #[inline]
   pub fn new() -> ScopeMap<K, V, RandomState> {
-    Self {
-      map: Default::default(),
-      layers: smallvec![Default::default()],
-      empty_key_count: 0,
-    }
+    Self::from_index_map(Default::default())
   }
   
   /// Creates an empty `ScopeMap` with a default hasher and the specified capacity.
@@ -92,20 +407,56 @@ impl<K, V, S: BuildHasher> ScopeMap<K, V, S> {
   /// Creates an empty `ScopeMap` with the specified hasher and a default capacity.
   #[inline]
   pub fn with_hasher(hash_builder: S) -> Self {
-    Self {
-      map: IndexMap::with_hasher(hash_builder),
-      layers: smallvec![Default::default()],
-      empty_key_count: 0,
-    }
+    Self::from_index_map(IndexMap::with_hasher(hash_builder))
   }
   
   /// Creates an empty `ScopeMap` with the specified hasher and capacity.
   #[inline]
   pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+    Self::from_index_map(IndexMap::with_capacity_and_hasher(capacity, hash_builder))
+  }
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Creates an empty `ScopeMap` with the specified hasher and a default capacity, using the inline capacities given by the type.
+  ///
+  /// Unlike [`ScopeMap::with_hasher`], this works for any `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn configured_with_hasher(hash_builder: S) -> Self {
+    Self::from_index_map(IndexMap::with_hasher(hash_builder))
+  }
+
+  /// Creates an empty `ScopeMap` with the specified hasher and capacity, using the inline capacities given by the type.
+  ///
+  /// Unlike [`ScopeMap::with_capacity_and_hasher`], this works for any `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn configured_with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+    Self::from_index_map(IndexMap::with_capacity_and_hasher(capacity, hash_builder))
+  }
+
+  /// Creates an empty `ScopeMap` with a default hasher and the specified capacity, using the inline capacities given by the type.
+  ///
+  /// Unlike [`ScopeMap::with_capacity`], this works for any hasher, `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn configured_with_capacity(capacity: usize) -> Self
+  where
+    S: Default,
+  {
+    Self::configured_with_capacity_and_hasher(capacity, Default::default())
+  }
+
+  /// Creates an empty `ScopeMap` that stores its entries in `map`.
+  #[inline]
+  fn from_index_map(map: IndexMap<K, ScopeMapValueStack<Var<V>, STACK>, S>) -> Self {
     Self {
-      map: IndexMap::with_capacity_and_hasher(capacity, hash_builder),
+      map,
       layers: smallvec![Default::default()],
+      generations: smallvec![0],
+      next_generation: 1,
+      layer_meta: Vec::new(),
       empty_key_count: 0,
+      policy: Default::default(),
+      reads: Default::default(),
     }
   }
   
@@ -115,6 +466,22 @@ impl<K, V, S: BuildHasher> ScopeMap<K, V, S> {
     self.map.capacity()
   }
 
+  /// Reports how much memory the map is using.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys and layers.
+  pub fn memory_stats(&self) -> MemoryStats {
+    MemoryStats {
+      key_count: self.map.len(),
+      key_capacity: self.map.capacity(),
+      stack_entries: self.map.values().map(|stack| stack.len()).sum(),
+      spilled_stacks: self.map.values().filter(|stack| stack.spilled()).count(),
+      layer_count: self.layers.len(),
+      layer_capacity: self.layers.capacity(),
+      layer_set_capacity: self.layers.iter().map(|layer| layer.capacity()).sum(),
+      layer_slot_count: self.layers.iter().map(|layer| layer.slot_count()).sum(),
+    }
+  }
+
   /// Returns `true` if the map is empty.
   #[inline]
   pub fn is_empty(&self) -> bool {
@@ -132,261 +499,702 @@ impl<K, V, S: BuildHasher> ScopeMap<K, V, S> {
   pub fn depth(&self) -> usize {
     self.layers.len()
   }
-}
 
-impl<K, V, S> ScopeMap<K, V, S> 
-where 
-  S: BuildHasher,
-{
-  /// Adds a new, empty layer.
-  ///
-  /// Computes in **O(1)** time.
+  /// Converts a depth (counted down from the top) into a height, or returns `None` if there is no layer at that depth.
   #[inline]
-  pub fn push_layer(&mut self) {
-    self.layers.push(Default::default())
+  fn height_at_depth(&self, depth: usize) -> Option<usize> {
+    self.depth().checked_sub(depth.checked_add(1)?)
   }
-  
-  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys/values.
-  /// Returns `true` if a layer was removed.
+
+  /// Gets the policy used by `try_define()` to handle keys that are already defined in the top layer.
+  #[inline]
+  pub fn redefinition_policy(&self) -> RedefinitionPolicy {
+    self.policy
+  }
+
+  /// Sets the policy used by `try_define()` to handle keys that are already defined in the top layer.
+  #[inline]
+  pub fn set_redefinition_policy(&mut self, policy: RedefinitionPolicy) {
+    self.policy = policy;
+  }
+
+  /// Returns `true` if read tracking is enabled.
+  #[inline]
+  pub fn is_tracking_reads(&self) -> bool {
+    self.reads.is_enabled()
+  }
+
+  /// Enables or disables read tracking.
   ///
-  /// Computes in **O(n)** time in relation to the number of keys stored in the removed layer.
+  /// While enabled, every binding resolved by the following lookups is marked as used:
+  /// `get()`, `get_mut()`, `get_key_value()`, `contains_key()`, `get_at_height()`, `get_at_height_mut()`,
+  /// `get_parent()`, `get_parent_depth()`, `get_parent_height()`, `get_parent_mut()`, `get_by_address()`, `get_mut_by_address()`,
+  /// `get_by_handle()`, `get_mut_by_handle()`, `captures_across()` and `capture()`.
+  /// Bindings that were never marked can be retrieved with `pop_layer_reporting()`.
+  /// The marks are stored in a separate table that is only allocated while tracking is enabled;
+  /// disabling tracking discards them.
   #[inline]
-  pub fn pop_layer(&mut self) -> bool {
-    // Don't allow the base layer to be popped
-    if self.layers.len() > 1 {
-      // Pop the keys found in the removed layer
-      for stack_index in self.layers.pop().unwrap() {
-        if let Some((_key, stack)) = self.map.get_index_mut(stack_index) {
-          let stack_just_emptied = stack.pop().is_some() && stack.is_empty();
-          if stack_just_emptied {
-            self.empty_key_count += 1;
-          }
+  pub fn set_read_tracking(&mut self, enabled: bool) {
+    self.reads.set_enabled(enabled);
+  }
+
+  /// Marks the binding at the specified map index and height as used if read tracking is enabled.
+  #[inline(always)]
+  fn mark_read(&self, index: usize, height: usize) {
+    self.reads.mark(index, height);
+  }
+
+  /// Gets the map's hasher.
+  #[inline]
+  pub(crate) fn hasher(&self) -> &S {
+    self.map.hasher()
+  }
+
+  /// Gets the key and value stack at the specified map index.
+  #[inline]
+  pub(crate) fn stack_at(&self, stack_index: usize) -> (&K, &[Var<V>]) {
+    let (key, stack) = self.map.get_index(stack_index).unwrap();
+    (key, stack)
+  }
+
+  /// Iterates over all keys along with the height of their topmost binding, without marking them as read.
+  #[inline]
+  pub(crate) fn top_heights(&self) -> impl Iterator<Item = (&'_ K, usize)> {
+    self.map.iter().filter_map(|(key, stack)| stack.last().map(|var| (key, var.layer)))
+  }
+
+  /// Verifies the consistency of the map's internal bookkeeping.
+  ///
+  /// Checks that every layer refers only to keys whose value stacks have an entry at that layer's height,
+  /// that every stack entry is referenced by its layer, that the heights in each stack strictly increase,
+  /// and that the count of empty stacks is accurate.
+  ///
+  /// This is intended for testing code that wraps or extends `ScopeMap`; a correct map always passes.
+  ///
+  /// Computes in **O(n)** time in relation to the total number of bindings.
+  pub fn check_invariants(&self) -> Result<(), InvariantError> {
+    if self.layers.is_empty() {
+      return Err(InvariantError("map has no base layer".to_owned()))
+    }
+    if self.generations.len() != self.layers.len() {
+      return Err(InvariantError(format!("{} layer generations recorded for {} layers", self.generations.len(), self.layers.len())))
+    }
+
+    for (height, layer) in self.layers.iter().enumerate() {
+      if layer.iter().count() != layer.len() {
+        return Err(InvariantError(format!("layer {} has {} occupied slots for {} keys", height, layer.iter().count(), layer.len())))
+      }
+      if layer.free.len() != layer.slot_count() - layer.len() {
+        return Err(InvariantError(format!("layer {} has {} free slots for {} vacant slots", height, layer.free.len(), layer.slot_count() - layer.len())))
+      }
+      for index in layer.iter() {
+        if layer.slot_of(index).and_then(|slot| layer.index_at(slot)) != Some(index) {
+          return Err(InvariantError(format!("layer {} has an inconsistent slot for map index {}", height, index)))
+        }
+        let stack = match self.map.get_index(index) {
+          Some((_, stack)) => stack,
+          None => return Err(InvariantError(format!("layer {} refers to missing map index {}", height, index))),
+        };
+        if find_in_stack(stack, height).is_none() {
+          return Err(InvariantError(format!("layer {} refers to map index {}, which has no entry at that height", height, index)))
         }
       }
-      return true;
     }
-    false
+
+    let mut empty_stacks = 0;
+    for (index, (_, stack)) in self.map.iter().enumerate() {
+      if stack.is_empty() {
+        empty_stacks += 1;
+      }
+      for (position, var) in stack.iter().enumerate() {
+        if position > 0 && stack[position - 1].layer >= var.layer {
+          return Err(InvariantError(format!("stack heights at map index {} do not strictly increase", index)))
+        }
+        if !self.layers.get(var.layer).is_some_and(|layer| layer.contains(index)) {
+          return Err(InvariantError(format!("map index {} has an entry at height {} missing from that layer", index, var.layer)))
+        }
+      }
+    }
+
+    if empty_stacks != self.empty_key_count {
+      return Err(InvariantError(format!("{} empty stacks, but empty_key_count is {}", empty_stacks, self.empty_key_count)))
+    }
+
+    Ok(())
   }
-}
 
-impl<K: Eq + Hash, V, S: BuildHasher> ScopeMap<K, V, S> {
-  
-  /// Returns `true` if the map contains the specified key in any layer.
-  ///
-  /// Computes in **O(1)** time.
+  /// Gets the map index, key and value of every binding in the layer at `height`, ordered by map index.
   #[inline]
-  pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
-  where
-    K: Borrow<Q>,
-    Q: Eq + Hash,
-  {
-    if let Some(stack) = self.map.get(key) {
-      !stack.is_empty()
-    } else {
-      false
+  pub(crate) fn layer_entries(&self, height: usize) -> Vec<(usize, &K, &V)> {
+    let mut indices: Vec<usize> = self.layers[height].iter().collect();
+    indices.sort_unstable();
+    indices
+      .into_iter()
+      .map(|index| {
+        let (key, stack) = self.stack_at(index);
+        let var = &stack[find_in_stack(stack, height).unwrap()];
+        (index, key, &var.value)
+      })
+      .collect()
+  }
+
+  /// Gets the value stack at the specified map index.
+  #[inline]
+  pub(crate) fn stack_at_mut(&mut self, stack_index: usize) -> &mut [Var<V>] {
+    self.map.get_index_mut(stack_index).unwrap().1
+  }
+
+  /// Removes the binding at `position` in the value stack at the specified map index, 
+  /// along with the stack's index from the binding's layer.
+  #[inline]
+  pub(crate) fn remove_from_stack(&mut self, stack_index: usize, position: usize) -> Var<V> {
+    let stack = self.map.get_index_mut(stack_index).unwrap().1;
+    let var = stack.remove(position);
+    if stack.is_empty() {
+      self.empty_key_count += 1;
     }
-  } 
+    self.layers[var.layer].remove(stack_index);
+    self.reads.unmark(stack_index, var.layer);
+    var
+  }
 
-  /// Returns `true` if the map contains the specified key at the top layer.
+  /// Replaces the value of the binding at `position` in the value stack at the specified map index and returns the previous value.
   ///
-  /// Computes in **O(1)** time.
+  /// The binding counts as a new, unread binding.
   #[inline]
-  pub fn contains_key_at_top<Q: ?Sized>(&self, key: &Q) -> bool
-  where
-    K: Borrow<Q>,
-    Q: Eq + Hash,
-  {
-    self.map.get_index_of(key).map_or(false, |i| self.layers.last().unwrap().contains(&i))
+  pub(crate) fn replace_in_stack(&mut self, stack_index: usize, position: usize, value: V) -> V {
+    let var = &mut self.map.get_index_mut(stack_index).unwrap().1[position];
+    self.reads.unmark(stack_index, var.layer);
+    mem::replace(&mut var.value, value)
   }
-  
-  /// Gets a reference to the topmost value associated with a key.
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Adds a new, empty layer.
   ///
   /// Computes in **O(1)** time.
   #[inline]
-  pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
-  where
-  K: Borrow<Q>,
-  Q: Eq + Hash,
-  {
-    self.map.get(key).and_then(|v| v.last().map(|v| &v.value))
+  pub fn push_layer(&mut self) {
+    self.layers.push(Default::default());
+    self.generations.push(self.next_generation);
+    self.next_generation += 1;
   }
 
-  /// Gets an iterator over references to all the values associated with a key, starting with the topmost and going down.
+  /// Adds a new, empty layer and returns its ID.
   ///
   /// Computes in **O(1)** time.
   #[inline]
-  pub fn get_all<Q: ?Sized>(&self, key: &Q) -> Option<impl Iterator<Item = &V>>
-  where K: Borrow<Q>,
-  Q: Eq + Hash
-  {
-    self.map.get(key).map(|stack| stack.iter().rev().map(|v| &v.value))
+  pub fn push_layer_id(&mut self) -> LayerId {
+    self.push_layer();
+    self.top_layer_id()
   }
-  
-  /// Gets a mutable reference to the topmost value associated with a key.
+
+  /// Gets the ID of the topmost layer.
+  #[inline]
+  pub fn top_layer_id(&self) -> LayerId {
+    LayerId::new(self.layers.len() - 1, *self.generations.last().unwrap())
+  }
+
+  /// Returns `true` if the layer identified by `id` still exists.
+  #[inline]
+  pub fn has_layer(&self, id: LayerId) -> bool {
+    self.generations.get(id.height()) == Some(&id.generation())
+  }
+
+  /// Adds a new, empty layer with the specified metadata attached.
   ///
   /// Computes in **O(1)** time.
   #[inline]
-  pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
-  where
-  K: Borrow<Q>,
-  Q: Eq + Hash,
-  {
-    self.map.get_mut(key).and_then(|v| v.last_mut().map(|v| &mut v.value))
+  pub fn push_layer_with_meta<M: Any + Send + Sync>(&mut self, meta: M) {
+    self.push_layer();
+    self.set_layer_meta(meta);
   }
 
-  /// Gets an iterator over mutable references to all the values associated with a key, starting with the topmost and going down.
+  /// Attaches metadata to the topmost layer, replacing any metadata it already has.
+  ///
+  /// The metadata is dropped when the layer is popped.
   ///
   /// Computes in **O(1)** time.
   #[inline]
-  pub fn get_all_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<impl Iterator<Item = &mut V>>
-  where K: Borrow<Q>,
-  Q: Eq + Hash
+  pub fn set_layer_meta<M: Any + Send + Sync>(&mut self, meta: M) {
+    let height = self.layers.len() - 1;
+    let meta: LayerMeta = Arc::new(meta);
+    match self.layer_meta.last_mut() {
+      Some((last, slot)) if *last == height => *slot = meta,
+      _ => self.layer_meta.push((height, meta)),
+    }
+  }
+
+  /// Gets the metadata of the layer `depth` layers below the top.
+  /// A depth of 0 refers to the top layer.
+  ///
+  /// Returns `None` if there is no such layer, or if its metadata is missing or not of type `M`.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers with metadata.
+  #[inline]
+  pub fn layer_meta<M: Any>(&self, depth: usize) -> Option<&M> {
+    let height = self.height_at_depth(depth)?;
+    let position = self.layer_meta.binary_search_by_key(&height, |(height, _)| *height).ok()?;
+    self.layer_meta[position].1.downcast_ref()
+  }
+
+  /// Finds the topmost layer whose metadata is of type `M`, returning the metadata and the layer's depth.
+  ///
+  /// Computes in **O(n)** time in relation to the number of layers with metadata.
+  #[inline]
+  pub fn find_layer_meta<M: Any>(&self) -> Option<(&M, usize)> {
+    let depth = self.depth();
+    self.layer_meta
+      .iter()
+      .rev()
+      .find_map(|(height, meta)| meta.downcast_ref().map(|meta| (meta, depth - 1 - height)))
+  }
+  
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys/values.
+  /// Returns `true` if a layer was removed.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys stored in the removed layer.
+  #[inline]
+  pub fn pop_layer(&mut self) -> bool {
+    self.pop_layer_with(|_, _| {})
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer), calling `f` with the map index and binding of each removed key.
+  /// Returns `true` if a layer was removed.
+  #[inline]
+  pub(crate) fn pop_layer_with(&mut self, mut f: impl FnMut(usize, Var<V>)) -> bool {
+    // Don't allow the base layer to be popped
+    if self.layers.len() > 1 {
+      self.generations.pop();
+      if matches!(self.layer_meta.last(), Some((height, _)) if *height == self.generations.len()) {
+        self.layer_meta.pop();
+      }
+      // Pop the keys found in the removed layer
+      for stack_index in self.layers.pop().unwrap().into_indices() {
+        if let Some((_key, stack)) = self.map.get_index_mut(stack_index) {
+          if let Some(var) = stack.pop() {
+            if stack.is_empty() {
+              self.empty_key_count += 1;
+            }
+            self.reads.unmark(stack_index, var.layer);
+            f(stack_index, var);
+          }
+        }
+      }
+      return true;
+    }
+    false
+  }
+
+  /// Gets the map indices of the bindings in the topmost layer that have not been read.
+  ///
+  /// Returns no indices while read tracking is disabled, since no reads are recorded then.
+  #[inline]
+  pub(crate) fn unread_in_top(&self) -> Vec<usize> {
+    if !self.reads.is_enabled() {
+      return vec![]
+    }
+    let height = self.layers.len() - 1;
+    self.layers[height]
+      .iter()
+      .filter(|&stack_index| !self.reads.is_marked(stack_index, height))
+      .collect()
+  }
+
+  /// Removes the topmost layer if its ID matches `id`.
+  ///
+  /// Returns an error if `id` does not identify the topmost layer, or if it identifies the base layer.
+  /// In both cases, the map is left unchanged.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys stored in the removed layer.
+  #[inline]
+  pub fn pop_layer_checked(&mut self, id: LayerId) -> Result<(), PopLayerError> {
+    let top = self.top_layer_id();
+    if top != id {
+      return Err(PopLayerError::Mismatch { expected: id, top })
+    }
+    if self.pop_layer() {
+      Ok(())
+    } else {
+      Err(PopLayerError::BaseLayer)
+    }
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys/values.
+  /// Returns the keys defined in the removed layer that were never read, or `None` if no layer was removed.
+  ///
+  /// While read tracking is disabled, no reads are recorded, so the returned list is always empty; see `set_read_tracking()`.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys stored in the removed layer.
+  #[inline]
+  pub fn pop_layer_reporting(&mut self) -> Option<Vec<&K>> {
+    let unused_indices = self.unread_in_top();
+    if !self.pop_layer() {
+      return None
+    }
+
+    Some(unused_indices
+      .into_iter()
+      .map(|i| self.map.get_index(i).unwrap().0)
+      .collect())
+  }
+}
+
+/// Lookups that accept any key type equivalent to `K`.
+///
+/// The public methods forward to these; they also allow composite keys to be queried without constructing an owned key.
+impl<K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  #[inline]
+  pub(crate) fn index_of_equivalent<Q: ?Sized + Hash + Equivalent<K>>(&self, key: &Q) -> Option<usize> {
+    self.map.get_index_of(key)
+  }
+
+  #[inline]
+  pub(crate) fn get_full_equivalent<Q: ?Sized + Hash + Equivalent<K>>(&self, key: &Q) -> Option<(usize, &K, &V)> {
+    let (index, key, stack) = self.map.get_full(key)?;
+    let var = stack.last()?;
+    self.mark_read(index, var.layer);
+    Some((index, key, &var.value))
+  }
+
+  #[inline]
+  pub(crate) fn contains_key_equivalent<Q: ?Sized + Hash + Equivalent<K>>(&self, key: &Q) -> bool {
+    if let Some((index, _key, stack)) = self.map.get_full(key) {
+      if let Some(var) = stack.last() {
+        self.mark_read(index, var.layer);
+        return true
+      }
+    }
+    false
+  }
+
+  #[inline]
+  #[allow(clippy::unnecessary_map_or)]
+  pub(crate) fn contains_key_at_top_equivalent<Q: ?Sized + Hash + Equivalent<K>>(&self, key: &Q) -> bool {
+    self.map.get_index_of(key).map_or(false, |i| self.layers.last().unwrap().contains(i))
+  }
+
+  #[inline]
+  pub(crate) fn get_equivalent<Q: ?Sized + Hash + Equivalent<K>>(&self, key: &Q) -> Option<&V> {
+    let (index, _key, stack) = self.map.get_full(key)?;
+    let var = stack.last()?;
+    self.mark_read(index, var.layer);
+    Some(&var.value)
+  }
+
+  /// Gets the topmost value associated with a key without marking it as read.
+  #[inline]
+  pub(crate) fn peek_equivalent<Q: ?Sized + Hash + Equivalent<K>>(&self, key: &Q) -> Option<&V> {
+    self.map.get(key)?.last().map(|var| &var.value)
+  }
+
+  #[inline]
+  pub(crate) fn get_mut_equivalent<Q: ?Sized + Hash + Equivalent<K>>(&mut self, key: &Q) -> Option<&mut V> {
+    let (index, _key, stack) = self.map.get_full_mut(key)?;
+    let var = stack.last_mut()?;
+    self.reads.mark_mut(index, var.layer);
+    Some(&mut var.value)
+  }
+
+  #[inline]
+  pub(crate) fn height_of_equivalent<Q: ?Sized + Hash + Equivalent<K>>(&self, key: &Q) -> Option<usize> {
+    self.map.get(key).and_then(|stack| stack.last()).map(|var| var.layer)
+  }
+
+  #[inline]
+  pub(crate) fn remove_equivalent<Q: ?Sized + Hash + Equivalent<K>>(&mut self, key: &Q) -> Option<V> {
+    if let Some((index, _key, stack)) = self.map.get_full_mut(key) {
+      if self.layers.last_mut().unwrap().remove(index) {
+        let taken = stack.pop();
+        if let Some(var) = &taken {
+          self.reads.unmark(index, var.layer);
+        }
+        let stack_just_emptied = taken.is_some() && stack.is_empty();
+        if stack_just_emptied {
+          self.empty_key_count += 1;
+        }
+        return taken.map(|v| v.value)
+      }
+    }
+    None
+  }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  
+  /// Returns `true` if the map contains the specified key in any layer.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.contains_key_equivalent(key)
+  }
+
+  /// Returns `true` if the map contains the specified key at the top layer.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn contains_key_at_top<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.contains_key_at_top_equivalent(key)
+  }
+  
+  /// Gets a reference to the topmost value associated with a key.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get<Q>(&self, key: &Q) -> Option<&V>
+  where
+  K: Borrow<Q>,
+  Q: ?Sized + Eq + Hash,
+  {
+    self.get_equivalent(key)
+  }
+
+  /// Gets references to the stored key and the topmost value associated with a key.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.get_full_equivalent(key).map(|(_, key, value)| (key, value))
+  }
+
+  /// Gets an iterator over references to all the values associated with a key, starting with the topmost and going down.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get_all<Q>(&self, key: &Q) -> Option<impl Iterator<Item = &V>>
+  where K: Borrow<Q>,
+  Q: ?Sized + Eq + Hash
+  {
+    self.map.get(key).map(|stack| stack.iter().rev().map(|v| &v.value))
+  }
+  
+  /// Gets a mutable reference to the topmost value associated with a key.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+  K: Borrow<Q>,
+  Q: ?Sized + Eq + Hash,
+  {
+    self.get_mut_equivalent(key)
+  }
+
+  /// Gets an iterator over mutable references to all the values associated with a key, starting with the topmost and going down.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get_all_mut<Q>(&mut self, key: &Q) -> Option<impl Iterator<Item = &mut V>>
+  where K: Borrow<Q>,
+  Q: ?Sized + Eq + Hash
   {
     self.map.get_mut(key).map(|stack| stack.iter_mut().rev().map(|v| &mut v.value))
   }
   
+  /// Gets a reference to the value associated with a key in the layer exactly `depth` layers below the top.
+  /// A depth of 0 refers to the top layer.
+  ///
+  /// Returns `None` if the key is not defined in that layer.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers containing the key.
+  #[inline]
+  pub fn get_at_depth<Q>(&self, key: &Q, depth: usize) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let height = self.height_at_depth(depth)?;
+    self.get_at_height(key, height)
+  }
+
+  /// Gets a reference to the value associated with a key in the layer at exactly `height`.
+  /// A height of 0 refers to the bottom layer.
+  ///
+  /// Returns `None` if the key is not defined in that layer.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers containing the key.
+  #[inline]
+  pub fn get_at_height<Q>(&self, key: &Q, height: usize) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let (index, _key, stack) = self.map.get_full(key)?;
+    let var = &stack[find_in_stack(stack, height)?];
+    self.mark_read(index, height);
+    Some(&var.value)
+  }
+
+  /// Gets a mutable reference to the value associated with a key in the layer at exactly `height`.
+  /// A height of 0 refers to the bottom layer.
+  ///
+  /// Returns `None` if the key is not defined in that layer.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers containing the key.
+  #[inline]
+  pub fn get_at_height_mut<Q>(&mut self, key: &Q, height: usize) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let (index, _key, stack) = self.map.get_full_mut(key)?;
+    let position = find_in_stack(stack, height)?;
+    self.reads.mark_mut(index, height);
+    Some(&mut stack[position].value)
+  }
+
+  /// Returns `true` if the key is defined in the layer exactly `depth` layers below the top.
+  /// A depth of 0 refers to the top layer.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers containing the key.
+  #[inline]
+  pub fn contains_key_at_depth<Q>(&self, key: &Q, depth: usize) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.height_at_depth(depth)
+      .is_some_and(|height| self.contains_key_at_height(key, height))
+  }
+
+  /// Returns `true` if the key is defined in the layer at exactly `height`.
+  /// A height of 0 refers to the bottom layer.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers containing the key.
+  #[inline]
+  pub fn contains_key_at_height<Q>(&self, key: &Q, height: usize) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.get(key).is_some_and(|stack| find_in_stack(stack, height).is_some())
+  }
+
   /// Gets a reference to a value `min_depth` layers below the topmost value associated with a key.
   /// Saturates to base layer.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to `min_depth`.
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
   #[inline]
-  pub fn get_parent<Q: ?Sized>(&self, key: &Q, min_depth: usize) -> Option<&V>
+  pub fn get_parent<Q>(&self, key: &Q, min_depth: usize) -> Option<&V>
   where
   K: Borrow<Q>,
-  Q: Eq + Hash,
+  Q: ?Sized + Eq + Hash,
   {
-    if let Some((var_index, _key, stack)) = self.map.get_full(key) { 
-      // If the skip count exceeds the stack size, it shouldn't matter because take() is self-truncating
-      let stack_skip_count = self
-      .layers
-      .iter()
-      .rev()
-      .take(min_depth)
-      .filter(|layer| layer.contains(&var_index))
-      .count();
-      return stack.iter().rev().nth(stack_skip_count).map(|v| &v.value)
-    }
-    None
+    let (index, _key, stack) = self.map.get_full(key)?;
+    let var = stack[..parent_count(stack, self.depth(), min_depth)].last()?;
+    self.mark_read(index, var.layer);
+    Some(&var.value)
   }
 
   /// Gets a reference to the value associated with a key at least `min_depth` layers below the topmost layer, as well as its associated depth.
   /// Saturates to base layer.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to `min_depth`.
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
   #[inline]
-  pub fn get_parent_depth<Q: ?Sized>(&self, key: &Q, min_depth: usize) -> Option<(&V, usize)>
+  pub fn get_parent_depth<Q>(&self, key: &Q, min_depth: usize) -> Option<(&V, usize)>
   where
   K: Borrow<Q>,
-  Q: Eq + Hash,
+  Q: ?Sized + Eq + Hash,
   {
-    if let Some((var_index, _key, stack)) = self.map.get_full(key) {
-      // If the skip count exceeds the stack size, it shouldn't matter because take() is self-truncating
-      let stack_skip_count = self
-      .layers
-      .iter()
-      .rev()
-      .take(min_depth)
-      .filter(|layer| layer.contains(&var_index))
-      .count();
-      return stack.iter().rev().nth(stack_skip_count).map(|v| (&v.value, invert_index(v.layer, self.depth())))
-    }
-    None
+    let (index, _key, stack) = self.map.get_full(key)?;
+    let var = stack[..parent_count(stack, self.depth(), min_depth)].last()?;
+    self.mark_read(index, var.layer);
+    Some((&var.value, invert_index(var.layer, self.depth())))
   }
 
   /// Gets a reference to the value associated with a key at least `min_depth` layers below the topmost layer, as well as its associated height.
   /// Saturates to base layer.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to `min_depth`.
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
   #[inline]
-  pub fn get_parent_height<Q: ?Sized>(&self, key: &Q, min_depth: usize) -> Option<(&V, usize)>
+  pub fn get_parent_height<Q>(&self, key: &Q, min_depth: usize) -> Option<(&V, usize)>
   where
   K: Borrow<Q>,
-  Q: Eq + Hash,
+  Q: ?Sized + Eq + Hash,
   {
-    if let Some((var_index, _key, stack)) = self.map.get_full(key) {
-      // If the skip count exceeds the stack size, it shouldn't matter because take() is self-truncating
-      let stack_skip_count = self
-      .layers
-      .iter()
-      .rev()
-      .take(min_depth)
-      .filter(|layer| layer.contains(&var_index))
-      .count();
-      return stack.iter().rev().nth(stack_skip_count).map(|v| (&v.value, v.layer))
-    }
-    None
+    let (index, _key, stack) = self.map.get_full(key)?;
+    let var = stack[..parent_count(stack, self.depth(), min_depth)].last()?;
+    self.mark_read(index, var.layer);
+    Some((&var.value, var.layer))
   }
 
   /// Gets an iterator over references to all values `min_depth` layers below the topmost value associated with a key.
   /// Saturates to base layer.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to `min_depth`.
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
   #[inline]
-  pub fn get_parents<Q: ?Sized>(&self, key: &Q, min_depth: usize) -> Option<impl Iterator<Item = &V>>
+  pub fn get_parents<Q>(&self, key: &Q, min_depth: usize) -> Option<impl Iterator<Item = &V>>
   where
   K: Borrow<Q>,
-  Q: Eq + Hash,
+  Q: ?Sized + Eq + Hash,
   {
-    if let Some((var_index, _key, stack)) = self.map.get_full(key) {
-      // If the skip count exceeds the stack size, it shouldn't matter because take() is self-truncating
-      let stack_skip_count = self
-      .layers
-      .iter()
-      .rev()
-      .take(min_depth)
-      .filter(|layer| layer.contains(&var_index))
-      .count();
-      return Some(stack.iter().rev().skip(stack_skip_count).map(|v| &v.value))
-    }
-    None
+    let stack = self.map.get(key)?;
+    let parents = &stack[..parent_count(stack, self.depth(), min_depth)];
+    Some(parents.iter().rev().map(|v| &v.value))
   }
   
   /// Gets a mutable reference to a value `min_depth` layers below the topmost value associated with a key.
   /// Saturates to base layer.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to `min_depth`.
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
   #[inline]
-  pub fn get_parent_mut<Q: ?Sized>(&mut self, key: &Q, min_depth: usize) -> Option<&mut V>
+  pub fn get_parent_mut<Q>(&mut self, key: &Q, min_depth: usize) -> Option<&mut V>
   where
     K: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
   {
-    if let Some((var_index, _key, stack)) = self.map.get_full_mut(key) {
-      // If the skip count exceeds the stack size, it shouldn't matter because take() is self-truncating
-      let stack_skip_count = self
-      .layers
-      .iter()
-      .rev()
-      .take(min_depth)
-      .filter(|layer| layer.contains(&var_index))
-      .count();
-      return stack.iter_mut().rev().nth(stack_skip_count).map(|v| &mut v.value)
-    }
-    None
+    let depth = self.depth();
+    let (index, _key, stack) = self.map.get_full_mut(key)?;
+    let count = parent_count(stack, depth, min_depth);
+    let var = stack[..count].last_mut()?;
+    self.reads.mark_mut(index, var.layer);
+    Some(&mut var.value)
   }
 
   /// Gets an iterator over mutable references to all values `min_depth` layers below the topmost value associated with a key.
   /// Saturates to base layer.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to `min_depth`.
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
   #[inline]
-  pub fn get_parents_mut<Q: ?Sized>(&mut self, key: &Q, min_depth: usize) -> Option<impl Iterator<Item = &mut V>>
+  pub fn get_parents_mut<Q>(&mut self, key: &Q, min_depth: usize) -> Option<impl Iterator<Item = &mut V>>
   where
     K: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
   {
-    if let Some((var_index, _key, stack)) = self.map.get_full_mut(key) {
-      // If the skip count exceeds the stack size, it shouldn't matter because take() is self-truncating
-      let stack_skip_count = self
-      .layers
-      .iter()
-      .rev()
-      .take(min_depth)
-      .filter(|layer| layer.contains(&var_index))
-      .count();
-      return Some(stack.iter_mut().rev().skip(stack_skip_count).map(|v| &mut v.value))
-    }
-    None
+    let depth = self.depth();
+    let stack = self.map.get_mut(key)?;
+    let count = parent_count(stack, depth, min_depth);
+    Some(stack[..count].iter_mut().rev().map(|v| &mut v.value))
+  }
+
+  /// Gets a cursor over all bindings of a key, starting at the topmost binding.
+  ///
+  /// Returns `None` if the key does not exist.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn stack_cursor<Q>(&self, key: &Q) -> Option<StackCursor<'_, K, V>>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map
+      .get_key_value(key)
+      .filter(|(_, stack)| !stack.is_empty())
+      .map(|(key, stack)| StackCursor::new(key, stack))
   }
 
   /// Gets the depth of the specified key (i.e. how many layers down from the top that the key first appears).
@@ -394,21 +1202,14 @@ impl<K: Eq + Hash, V, S: BuildHasher> ScopeMap<K, V, S> {
   ///
   /// Returns `None` if the key does not exist.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to layer count.
+  /// Computes in **O(1)** time.
   #[inline]
-  pub fn depth_of<Q: ?Sized>(&self, key: &Q) -> Option<usize> 
+  pub fn depth_of<Q>(&self, key: &Q) -> Option<usize> 
   where
     K: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
   {
-    if let Some(index) = self.map.get_index_of(key) {
-      for (depth, layer) in self.layers.iter().rev().enumerate() {
-        if layer.contains(&index) {
-          return Some(depth);
-        }
-      }
-    }
-    None
+    self.height_of(key).map(|height| invert_index(height, self.depth()))
   }
 
   /// Gets the height of the specified key (i.e. how many layers up from the bottom that the key last appears).
@@ -416,251 +1217,1245 @@ impl<K: Eq + Hash, V, S: BuildHasher> ScopeMap<K, V, S> {
   ///
   /// Returns `None` if the key does not exist.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to layer count.
+  /// Computes in **O(1)** time.
   #[inline]
-  pub fn height_of<Q: ?Sized>(&self, key: &Q) -> Option<usize> 
+  pub fn height_of<Q>(&self, key: &Q) -> Option<usize> 
   where
     K: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
   {
-    if let Some(index) = self.map.get_index_of(key) {
-      for (height, layer) in self.layers.iter().enumerate().rev() {
-        if layer.contains(&index) {
-          return Some(height);
-        }
-      }
-    }
-    None
+    self.height_of_equivalent(key)
   }
   
   /// Adds the specified entry to the topmost layer.
+  ///
+  /// Replaces any existing binding of the key in the topmost layer, regardless of the redefinition policy; use `try_define()` to apply it.
   #[inline]
   pub fn define(&mut self, key: K, value: V) {
-    let height = self.depth();
-    let entry = self.map.entry(key);
+    self.define_reporting(key, value);
+  }
+
+  /// Adds all of the specified entries to the topmost layer.
+  ///
+  /// Space for the entries is reserved up front, based on the iterator's lower size hint.
+  /// If the same key appears more than once, the last entry wins, just as with repeated calls to `define()`.
+  #[inline]
+  pub fn define_many(&mut self, entries: impl IntoIterator<Item = (K, V)>) {
+    let entries = entries.into_iter();
+    let additional = entries.size_hint().0;
+    self.map.reserve(additional);
+    self.layers.last_mut().unwrap().reserve(additional);
+    for (key, value) in entries {
+      self.define_top(key, value);
+    }
+  }
+
+  /// Adds a new layer containing the specified entries.
+  ///
+  /// Duplicate keys are handled as in `define_many()`.
+  #[inline]
+  pub fn push_layer_from(&mut self, entries: impl IntoIterator<Item = (K, V)>) {
+    self.push_layer();
+    self.define_many(entries);
+  }
+
+  /// Adds the specified entry to the topmost layer and reports how it relates to existing bindings of the key.
+  ///
+  /// If the key is already defined in the topmost layer, the map's [`RedefinitionPolicy`] decides the result:
+  /// * `Allow` and `ReplaceAndWarn` replace the value and return the previous one in [`DefineOutcome::Replaced`].
+  /// * `Reject` leaves the map unchanged and returns the key and value in a [`RedefinitionError`].
+  #[inline]
+  pub fn try_define(&mut self, key: K, value: V) -> Result<DefineOutcome<V>, RedefinitionError<K, V>> {
+    self.try_define_index(key, value).map(|(_, outcome)| outcome)
+  }
+
+  /// Like `try_define()`, but also returns the key's map index when the entry is defined.
+  #[inline]
+  pub(crate) fn try_define_index(&mut self, key: K, value: V) -> Result<(usize, DefineOutcome<V>), RedefinitionError<K, V>> {
+    let top = self.depth() - 1;
+    let mut shadowed_height = None;
+    if let Some((index, _key, stack)) = self.map.get_full_mut(&key) {
+      if self.layers[top].contains(index) {
+        match self.policy {
+          RedefinitionPolicy::Allow | RedefinitionPolicy::ReplaceAndWarn => {
+            let previous = mem::replace(&mut stack.last_mut().unwrap().value, value);
+            self.reads.unmark(index, top);
+            return Ok((index, DefineOutcome::Replaced(previous)))
+          },
+          RedefinitionPolicy::Reject => {
+            return Err(RedefinitionError { key, value, height: top })
+          },
+        }
+      }
+      shadowed_height = stack.last().map(|var| var.layer);
+    }
+    let index = self.define_index(key, value);
+    Ok((index, shadowed_height.map_or(DefineOutcome::New, |height| DefineOutcome::Shadowed { height })))
+  }
+
+  /// Adds the specified entry to the topmost layer.
+  /// If the new binding hides a binding in a lower layer, returns the height and value of the hidden binding.
+  #[inline]
+  pub fn define_reporting(&mut self, key: K, value: V) -> Option<ShadowInfo<'_, V>> {
+    let (_, stack) = self.define_top(key, value);
+    stack.iter().rev().nth(1).map(|var| ShadowInfo { height: var.layer, value: &var.value })
+  }
+
+  /// Adds the specified entry to the topmost layer and returns the key's map index.
+  #[inline]
+  pub(crate) fn define_index(&mut self, key: K, value: V) -> usize {
+    self.define_top(key, value).0
+  }
+
+  /// Adds the specified entry to the topmost layer and returns the key's map index and value stack.
+  #[inline]
+  fn define_top(&mut self, key: K, value: V) -> (usize, &ScopeMapValueStack<Var<V>, STACK>) {
+    let height = self.depth();
+    let entry = self.map.entry(key);
     let var_index = entry.index();
     let is_stack_new = matches!(entry, indexmap::map::Entry::Vacant(..));
-    let stack = entry.or_insert_with(Default::default);
+    let stack = entry.or_default();
     let is_new_in_layer = self.layers.last_mut().unwrap().insert(var_index);
     let was_stack_empty = stack.is_empty();
-    
+
     if is_new_in_layer {
-      stack.push(Var {
-        value,
-        layer: height - 1,
-      });
+      stack.push(Var::new(value, height - 1, next_stamp(&mut self.next_generation)));
       if was_stack_empty && !is_stack_new {
         self.empty_key_count -= 1;
       }
     } else {
       stack.last_mut().unwrap().value = value;
+      self.reads.unmark(var_index, height - 1);
+    }
+
+    (var_index, stack)
+  }
+
+  /// Stores `key` in place of the equal key already in the map, leaving every binding as it is, and returns the previously stored key.
+  ///
+  /// Returns `Err(key)` if no equal key is visible.
+  #[inline]
+  pub(crate) fn replace_visible_key(&mut self, key: K) -> Result<K, K> {
+    match self.map.get_full_mut2(&key) {
+      Some((_, stored_key, stack)) if !stack.is_empty() => Ok(mem::replace(stored_key, key)),
+      _ => Err(key),
     }
   }
 
+  /// Removes every binding of the specified key, along with its entry in the key table, and returns the stored key and its bindings.
+  ///
+  /// The last key in the key table is moved into the removed entry's place, keeping its slot in every layer.
+  ///
+  /// Computes in **O(n)** time in relation to the number of layers containing either key.
+  pub(crate) fn remove_key_entry<Q>(&mut self, key: &Q) -> Option<(K, ScopeMapValueStack<Var<V>, STACK>)>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let index = match self.map.get_full(key) {
+      Some((index, _, stack)) if !stack.is_empty() => index,
+      _ => return None,
+    };
+    let (key, stack) = self.map.swap_remove_index(index).unwrap();
+    for var in stack.iter() {
+      self.layers[var.layer].remove(index);
+      self.reads.unmark(index, var.layer);
+    }
+    let moved_index = self.map.len();
+    if let Some((_, moved_stack)) = self.map.get_index(index) {
+      for var in moved_stack.iter() {
+        self.layers[var.layer].reindex(moved_index, index);
+        self.reads.reindex(moved_index, index, var.layer);
+      }
+    }
+    Some((key, stack))
+  }
+
+  /// Adds the specified entry to the topmost layer and returns a handle to the new binding.
+  #[inline]
+  pub fn define_with_handle(&mut self, key: K, value: V) -> BindingHandle {
+    let (index, stack) = self.define_top(key, value);
+    let position = stack.len() - 1;
+    let stamp = stack[position].stamp;
+    BindingHandle::new(index, position, self.top_layer_id(), stamp)
+  }
+
   /// Adds the specified entry in the layer `min_depth` layers from the top. Saturates to base layer.
+  ///
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
   #[inline]
   pub fn define_parent(&mut self, key: K, value: V, min_depth: usize) {
-    let height = self.depth();
+    let height = (self.depth() - 1).saturating_sub(min_depth);
+    self.define_at_height(key, value, height);
+  }
+
+  /// Adds the specified entry in the layer `min_depth` layers from the top and returns a handle to the new binding.
+  /// Saturates to base layer.
+  ///
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
+  #[inline]
+  pub fn define_parent_with_handle(&mut self, key: K, value: V, min_depth: usize) -> BindingHandle {
+    let height = (self.depth() - 1).saturating_sub(min_depth);
+    let index = self.define_at_height(key, value, height);
+    self.handle_at_height(index, height)
+  }
+
+  /// Gets a handle to the binding at `height` in the stack at map index `stack_index`, which must exist.
+  #[inline]
+  pub(crate) fn handle_at_height(&self, stack_index: usize, height: usize) -> BindingHandle {
+    let stack = self.stack_at(stack_index).1;
+    let position = find_in_stack(stack, height).unwrap();
+    BindingHandle::new(stack_index, position, LayerId::new(height, self.generations[height]), stack[position].stamp)
+  }
+
+  /// Adds the specified entry to the layer identified by `id`.
+  ///
+  /// Returns `false` and discards the entry if the layer no longer exists.
+  ///
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
+  #[inline]
+  pub fn define_in(&mut self, id: LayerId, key: K, value: V) -> bool {
+    if !self.has_layer(id) {
+      return false
+    }
+    self.define_at_height(key, value, id.height());
+    true
+  }
+
+  /// Adds the specified entry to the layer at `height`, which must exist, and returns the key's map index.
+  #[inline]
+  pub(crate) fn define_at_height(&mut self, key: K, value: V, height: usize) -> usize {
     let entry = self.map.entry(key);
     let stack_index = entry.index();
     let is_stack_new = matches!(entry, indexmap::map::Entry::Vacant(..));
-    let stack = entry.or_insert_with(Default::default);
-    let is_new_in_layer = self.layers
-      .iter_mut()
-      .nth_back(min_depth.min(height - 1))
-      .unwrap()
-      .insert(stack_index);
+    let stack = entry.or_default();
+    let is_new_in_layer = self.layers[height].insert(stack_index);
     let was_stack_empty = stack.is_empty();
-
-    let stack_skip_count = self
-    .layers
-    .iter()
-    .rev()
-    .take(min_depth)
-    .filter(|layer| layer.contains(&stack_index))
-    .count();
-
-    let index_in_stack = invert_index(stack_skip_count, stack.len());
+    let index_in_stack = stack.partition_point(|var| var.layer < height);
 
     if is_new_in_layer {
       // If the key is new in this layer, we need to insert the value into the key's stack
-      stack.insert(index_in_stack, Var {
-        value,
-        layer: height.saturating_sub(min_depth + 1),
-      });
+      stack.insert(index_in_stack, Var::new(value, height, next_stamp(&mut self.next_generation)));
 
       if was_stack_empty && !is_stack_new {
         self.empty_key_count -= 1;
       }
     } else {
-      // If the key is already in the layer, just replace the value
+      // If the key is already in the layer, replace the binding
       stack[index_in_stack].value = value;
+      self.reads.unmark(stack_index, height);
     }
+
+    stack_index
+  }
+
+  /// Gets the address of the topmost binding of the specified key.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn resolve<Q>(&self, key: &Q) -> Option<Address>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let (index, _key, stack) = self.map.get_full(key)?;
+    let var = stack.last()?;
+    let slot = self.layers[var.layer].slot_of(index).unwrap();
+    Some(Address::new(self.depth() - 1 - var.layer, slot, var.stamp))
+  }
+
+  /// Gets the number of slots in the layer `depth` layers below the top, or 0 if there is no such layer.
+  ///
+  /// Slots left vacant by removed bindings are included until they are reused by a later definition or the layer is cleared.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn slot_count(&self, depth: usize) -> usize {
+    self.height_at_depth(depth).map_or(0, |height| self.layers[height].slot_count())
+  }
+
+  /// Gets a reference to the value of the binding at `address`.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers containing the binding's key.
+  #[inline]
+  pub fn get_by_address(&self, address: Address) -> Option<&V> {
+    let (index, position, height) = self.binding_at_address(address)?;
+    let var = &self.map.get_index(index).unwrap().1[position];
+    self.mark_read(index, height);
+    Some(&var.value)
+  }
+
+  /// Gets a mutable reference to the value of the binding at `address`.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers containing the binding's key.
+  #[inline]
+  pub fn get_mut_by_address(&mut self, address: Address) -> Option<&mut V> {
+    let (index, position, height) = self.binding_at_address(address)?;
+    let var = &mut self.map.get_index_mut(index).unwrap().1[position];
+    self.reads.mark_mut(index, height);
+    Some(&mut var.value)
+  }
+
+  /// Gets the map index, stack position and height of the binding at `address`, without marking it as read.
+  ///
+  /// Returns `None` if the slot is vacant or has been reused by another binding.
+  #[inline]
+  pub(crate) fn binding_at_address(&self, address: Address) -> Option<(usize, usize, usize)> {
+    let height = self.height_at_depth(address.depth)?;
+    let index = self.layers[height].index_at(address.slot)?;
+    let stack = self.stack_at(index).1;
+    let position = find_in_stack(stack, height).unwrap();
+    (stack[position].stamp == address.stamp).then_some((index, position, height))
+  }
+
+  /// Gets a handle to the topmost binding of the specified key.
+  #[inline]
+  pub fn handle_of<Q>(&self, key: &Q) -> Option<BindingHandle>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let (index, _key, stack) = self.map.get_full(key)?;
+    let var = stack.last()?;
+    Some(BindingHandle::new(index, stack.len() - 1, LayerId::new(var.layer, self.generations[var.layer]), var.stamp))
+  }
+
+  /// Gets a reference to the value of the binding referred to by `handle`.
+  ///
+  /// Returns `None` if the binding's layer has been popped or the binding has been removed.
+  ///
+  /// Computes in **O(1)** time, or **O(log n)** in relation to the number of layers containing the key
+  /// if bindings have since been added to lower layers with `define_parent()`.
+  #[inline]
+  pub fn get_by_handle(&self, handle: BindingHandle) -> Option<&V> {
+    let position = self.position_of_handle(handle)?;
+    let var = &self.map.get_index(handle.index()).unwrap().1[position];
+    self.mark_read(handle.index(), var.layer);
+    Some(&var.value)
+  }
+
+  /// Gets a mutable reference to the value of the binding referred to by `handle`.
+  ///
+  /// Returns `None` if the binding's layer has been popped or the binding has been removed.
+  ///
+  /// Computes in **O(1)** time, or **O(log n)** in relation to the number of layers containing the key
+  /// if bindings have since been added to lower layers with `define_parent()`.
+  #[inline]
+  pub fn get_mut_by_handle(&mut self, handle: BindingHandle) -> Option<&mut V> {
+    let position = self.position_of_handle(handle)?;
+    let var = &mut self.map.get_index_mut(handle.index()).unwrap().1[position];
+    self.reads.mark_mut(handle.index(), var.layer);
+    Some(&mut var.value)
+  }
+
+  /// Finds the current position of a handle's binding in its value stack.
+  #[inline]
+  pub(crate) fn position_of_handle(&self, handle: BindingHandle) -> Option<usize> {
+    let layer = handle.layer();
+    if !self.has_layer(layer) {
+      return None
+    }
+    let (_, stack) = self.map.get_index(handle.index())?;
+    let position = match stack.get(handle.position()) {
+      Some(var) if var.layer == layer.height() => handle.position(),
+      _ => find_in_stack(stack, layer.height())?,
+    };
+    // A binding removed and redefined in the same layer gets a new stamp
+    (stack[position].stamp == handle.stamp()).then_some(position)
   }
 
   /// Removes the entry with the specified key from the topmost layer and returns its value.
   #[inline]
-  pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+  pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
   where
     K: Borrow<Q>,
-    Q: Eq + Hash, 
+    Q: ?Sized + Eq + Hash,
   {
-    if let Some((index, _key, stack)) = self.map.get_full_mut(key) {
-      if self.layers.last_mut().unwrap().remove(&index) {
-        let taken = stack.pop();
-        let stack_just_emptied = taken.is_some() && stack.is_empty();
-        if stack_just_emptied {
+    self.remove_equivalent(key)
+  }
+  
+  /// Removes all entries in the topmost layer.
+  #[inline]
+  pub fn clear_top(&mut self) {
+    self.clear_top_with(|_, _| {})
+  }
+
+  /// Removes all entries in the topmost layer, calling `f` with the map index and binding of each removed key.
+  #[inline]
+  pub(crate) fn clear_top_with(&mut self, mut f: impl FnMut(usize, Var<V>)) {
+    for stack_index in self.layers.last_mut().unwrap().vacate() {
+      let stack = self.map.get_index_mut(stack_index).unwrap().1;
+      if let Some(var) = stack.pop() {
+        if stack.is_empty() {
           self.empty_key_count += 1;
         }
-        return taken.map(|v| v.value)
+        self.reads.unmark(stack_index, var.layer);
+        f(stack_index, var);
       }
     }
-    None
   }
-  
-  /// Removes all entries in the topmost layer.
-  #[inline]
-  pub fn clear_top(&mut self) {
-    for stack_index in self.layers.last_mut().unwrap().drain() {
-      let stack = self.map.get_index_mut(stack_index).unwrap().1;
-      let stack_just_emptied = stack.pop().is_some() && stack.is_empty();
-      if stack_just_emptied {
-        self.empty_key_count += 1;
-      }
+  
+  /// Removes all elements and additional layers.
+  #[inline]
+  pub fn clear_all(&mut self) {
+    self.map.clear();
+    self.layers.clear();
+    self.layers.push(Default::default());
+    self.generations.clear();
+    // The fresh base layer gets a new generation so that old IDs and handles stop resolving
+    self.generations.push(self.next_generation);
+    self.next_generation += 1;
+    self.layer_meta.clear();
+    self.empty_key_count = 0;
+    self.reads.clear();
+  }
+
+  /// Iterates over all key-value pairs in arbitrary order.
+  ///
+  /// The iterator element type is `(&'a K, &'a V)`.
+  #[inline]
+  pub fn iter(&self) -> impl Iterator<Item = (&'_ K, &'_ V)> {
+    self.map
+      .iter()
+      .filter_map(|(key, stack)| stack.last().map(|var| (key, &var.value)))
+  }
+
+  /// Iterates over all key-value pairs in the topmost layer in arbitrary order.
+  ///
+  /// The iterator element type is `(&'a K, &'a V)`.
+  #[inline]
+  pub fn iter_top(&self) -> impl Iterator<Item = (&'_ K, &'_ V)> {
+    self.layers
+      .last()
+      .unwrap()
+      .iter()
+      .filter_map(move |i| self.map
+        .get_index(i)
+        .map(|(key, stack)| (key, &stack.last().unwrap().value))
+      )
+  }
+
+  /// Iterates over all keys in the topmost layer that hide a binding in a lower layer, in arbitrary order.
+  /// Each key is paired with the height and value of the binding directly beneath it.
+  ///
+  /// The iterator element type is `(&'a K, ShadowInfo<'a, V>)`.
+  #[inline]
+  pub fn shadowed_in_top(&self) -> impl Iterator<Item = (&'_ K, ShadowInfo<'_, V>)> {
+    self.layers
+      .last()
+      .unwrap()
+      .iter()
+      .filter_map(move |i| {
+        let (key, stack) = self.map.get_index(i)?;
+        stack.iter().rev().nth(1).map(|var| (key, ShadowInfo { height: var.layer, value: &var.value }))
+      })
+  }
+
+  /// Iterates over all key-value pairs in arbitrary order, allowing mutation of the values.
+  ///
+  /// The iterator element type is `(&'a K, &'a mut V)`.
+  #[inline]
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = (&'_ K, &'_ mut V)> {
+    self.map
+      .iter_mut()
+      .filter_map(|(key, stack)| stack.last_mut().map(|var| (key, &mut var.value)))
+  }
+
+  /// Iterates over all keys in arbitrary order.
+  ///
+  /// The iterator element type is `&'a K`.
+  #[inline]
+  pub fn keys(&self) -> impl Iterator<Item = &'_ K> {
+    self.map
+      .iter()
+      .filter(|(_, stack)| !stack.is_empty())
+      .map(|(key, _)| key)
+  }
+
+  /// Returns `true` if no keys are defined in the topmost layer.
+  #[inline]
+  pub(crate) fn is_top_empty(&self) -> bool {
+    self.layers.last().unwrap().len() == 0
+  }
+
+  /// Iterates over all keys in the topmost layer in arbitrary order.
+  ///
+  /// The iterator element type is `&'a K`.
+  #[inline]
+  pub fn keys_top(&self) -> impl Iterator<Item = &'_ K> {
+    self.layers
+      .last()
+      .unwrap()
+      .iter()
+      .map(move |i| self.map.get_index(i).unwrap().0)
+  }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Reserves capacity for at least `additional` more keys.
+  #[inline]
+  pub fn reserve(&mut self, additional: usize) {
+    self.map.reserve(additional)
+  }
+
+  /// Reserves capacity for at least `additional` more layers.
+  #[inline]
+  pub fn reserve_layers(&mut self, additional: usize) {
+    self.layers.reserve(additional);
+    self.generations.reserve(additional);
+  }
+
+  /// Reserves capacity for at least `additional` more keys to be defined in the topmost layer.
+  #[inline]
+  pub fn reserve_in_top(&mut self, additional: usize) {
+    self.layers.last_mut().unwrap().reserve(additional)
+  }
+
+  /// Removes keys with no remaining bindings from the key table, then shrinks the key table, value stacks and layers as much as possible.
+  ///
+  /// The remaining keys keep their relative order, and the slots of bindings in each layer are unchanged.
+  /// Handles to bindings of keys that moved within the key table stop resolving.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys and layers.
+  pub fn shrink_to_fit(&mut self) {
+    if self.empty_key_count > 0 {
+      let mut kept = 0;
+      let new_indices: Vec<Option<usize>> = self.map.values()
+        .map(|stack| {
+          if stack.is_empty() {
+            return None
+          }
+          kept += 1;
+          Some(kept - 1)
+        })
+        .collect();
+      self.map.retain(|_, stack| !stack.is_empty());
+      for layer in self.layers.iter_mut() {
+        layer.remap(&new_indices);
+      }
+      self.reads.remap(&new_indices);
+      self.empty_key_count = 0;
+    }
+    self.map.shrink_to_fit();
+    for stack in self.map.values_mut() {
+      stack.shrink_to_fit();
+    }
+    for layer in self.layers.iter_mut() {
+      layer.shrink_to_fit();
+    }
+    self.layers.shrink_to_fit();
+    self.generations.shrink_to_fit();
+  }
+
+  /// Finds which of the specified keys are visible through bindings below the layer at `barrier_height`,
+  /// returning each such key along with the height of its visible binding.
+  ///
+  /// Keys that are undefined, or whose visible binding is at or above the barrier, are skipped.
+  /// Each key is reported at most once, in the order it first appears in `keys`.
+  pub fn captures_across<'q, Q>(&self, barrier_height: usize, keys: impl IntoIterator<Item = &'q Q>) -> Vec<(&K, usize)>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash + 'q,
+  {
+    let mut seen = HashSet::new();
+    let mut captures = vec![];
+    for key in keys {
+      if let Some((index, key, stack)) = self.map.get_full(key) {
+        if let Some(var) = stack.last() {
+          if var.layer < barrier_height && seen.insert(index) {
+            self.mark_read(index, var.layer);
+            captures.push((key, var.layer));
+          }
+        }
+      }
+    }
+    captures
+  }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone, S: Clone + BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Creates a new map whose base layer contains clones of the currently visible bindings of the specified keys.
+  ///
+  /// Only the topmost binding of each key is copied, so shadowed bindings are not carried over.
+  /// Undefined keys are skipped.
+  pub fn capture<'q, Q>(&self, keys: impl IntoIterator<Item = &'q Q>) -> Self
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash + 'q,
+  {
+    let mut captured = Self::from_index_map(IndexMap::with_hasher(self.map.hasher().clone()));
+    for key in keys {
+      if let Some((index, key, stack)) = self.map.get_full(key) {
+        if let Some(var) = stack.last() {
+          self.mark_read(index, var.layer);
+          captured.define(key.clone(), var.value.clone());
+        }
+      }
+    }
+    captured
+  }
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Attaches an observer that is notified of every change made through the returned wrapper.
+  #[inline]
+  pub fn observe<O>(self, observer: O) -> ObservedScopeMap<K, V, O, S, STACK, LAYERS> {
+    ObservedScopeMap::new(self, observer)
+  }
+
+  /// Adds a new, empty layer and returns a guard that removes it when dropped.
+  ///
+  /// The guard dereferences to the map, and removes the layer on every exit path, including early returns and panics.
+  #[inline]
+  pub fn scope(&mut self) -> ScopeGuard<'_, K, V, S, STACK, LAYERS> {
+    ScopeGuard::new(self)
+  }
+
+  /// Adds a new, empty layer, calls `f` with the map, and removes the layer afterwards.
+  /// Returns the result of `f`.
+  ///
+  /// The layer is removed even if `f` panics.
+  #[inline]
+  pub fn with_layer<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+    let mut guard = self.scope();
+    f(&mut guard)
+  }
+
+  /// Adds a new, empty layer with the specified metadata attached, calls `f` with the map, and removes the layer afterwards.
+  /// Returns the result of `f`.
+  ///
+  /// The layer and its metadata are removed even if `f` panics.
+  #[inline]
+  pub fn with_layer_meta<M: Any + Send + Sync, R>(&mut self, meta: M, f: impl FnOnce(&mut Self) -> R) -> R {
+    let mut guard = self.scope();
+    guard.set_layer_meta(meta);
+    f(&mut guard)
+  }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Gets a cursor over all bindings of a key that can replace and remove them, starting at the topmost binding.
+  ///
+  /// Returns `None` if the key does not exist.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn stack_cursor_mut<Q>(&mut self, key: &Q) -> Option<StackCursorMut<'_, K, V, S, STACK, LAYERS>>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    match self.map.get_full(key) {
+      Some((stack_index, _, stack)) if !stack.is_empty() => Some(StackCursorMut::new(self, stack_index)),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::ScopeObserver;
+
+  #[test]
+  fn map_init() {
+    let map: ScopeMap<String, i32> = ScopeMap::new();
+    assert_eq!(0, map.len());
+    assert_eq!(1, map.depth());
+    assert!(map.is_empty());
+  }
+
+  #[test]
+  fn map_default() {
+    let map: ScopeMap<String, i32> = Default::default();
+    assert_eq!(0, map.len());
+    assert_eq!(1, map.depth());
+    assert!(map.is_empty());
+  }
+
+  #[test]
+  fn map_capacity() {
+    let map: ScopeMap<String, i32> = ScopeMap::with_capacity(32);
+    assert_eq!(32, map.capacity());
+  }
+
+  #[test]
+  fn map_define() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 123);
+    assert_eq!(1, map.len());
+    assert_eq!(Some(&123), map.get("foo"));
+  }
+
+  #[test]
+  fn map_define_parent() {
+    let mut map = ScopeMap::new();
+    map.push_layer();
+    map.define_parent("foo", 123, 1);
+    assert_eq!(Some(1), map.depth_of("foo"));
+    assert_eq!(Some(&123), map.get_parent("foo", 1));
+    assert_eq!(None, map.get_parent("foo", 2));
+  }
+
+  #[test]
+  fn map_define_parent_after_child() {
+    let mut map = ScopeMap::new();
+    map.push_layer();
+    map.define("foo", 456);
+    map.define_parent("foo", 123, 1);
+    assert_eq!(Some(&456), map.get("foo"));
+    assert_eq!(Some(&123), map.get_parent("foo", 1));
+    map.pop_layer();
+    assert_eq!(Some(&123), map.get("foo"));
+  }
+
+  #[test]
+  fn map_define_parent_between() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.push_layer();
+    map.push_layer();
+    map.define("foo", 3);
+    map.define_parent("foo", 2, 1);
+    let values = map.get_all("foo").map(|values| values.cloned().collect::<Vec<i32>>());
+    assert_eq!(Some(vec![3, 2, 1]), values);
+    assert_eq!(Some((&2, 1)), map.get_parent_height("foo", 1));
+    assert_eq!(Some((&1, 0)), map.get_parent_height("foo", 2));
+    map.define_parent("foo", 20, 1);
+    assert_eq!(Some(&20), map.get_at_height("foo", 1));
+    assert_eq!(3, map.get_all("foo").unwrap().count());
+  }
+
+  #[test]
+  fn map_define_parent_saturated() {
+    let mut map = ScopeMap::new();
+    map.push_layer();
+    map.define_parent("foo", 123, 3);
+    assert_eq!(Some(1), map.depth_of("foo"));
+    assert_eq!(Some(&123), map.get("foo"));
+  }
+
+  #[test]
+  fn map_try_define_new() {
+    let mut map = ScopeMap::new();
+    assert_eq!(Ok(DefineOutcome::New), map.try_define("foo", 123));
+    assert_eq!(Some(&123), map.get("foo"));
+  }
+
+  #[test]
+  fn map_try_define_shadowed() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 123);
+    map.push_layer();
+    map.push_layer();
+    assert_eq!(Ok(DefineOutcome::Shadowed { height: 0 }), map.try_define("foo", 456));
+    assert_eq!(Some(&456), map.get("foo"));
+  }
+
+  #[test]
+  fn map_try_define_allow() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 123);
+    map.push_layer();
+    map.define("foo", 456);
+    assert_eq!(Ok(DefineOutcome::Replaced(456)), map.try_define("foo", 789));
+    assert_eq!(Some(&789), map.get("foo"));
+    assert_eq!(Some(&123), map.get_parent("foo", 1));
+  }
+
+  #[test]
+  fn map_try_define_reject() {
+    let mut map = ScopeMap::new();
+    map.set_redefinition_policy(RedefinitionPolicy::Reject);
+    map.push_layer();
+    map.define("foo", 123);
+    let err = map.try_define("foo", 456).unwrap_err();
+    assert_eq!(("foo", 456, 1), (err.key, err.value, err.height));
+    assert_eq!(Some(&123), map.get("foo"));
+  }
+
+  #[test]
+  fn map_try_define_replace_and_warn() {
+    let mut map = ScopeMap::new();
+    map.set_redefinition_policy(RedefinitionPolicy::ReplaceAndWarn);
+    map.define("foo", 123);
+    assert_eq!(Ok(DefineOutcome::Replaced(123)), map.try_define("foo", 456));
+    assert_eq!(Some(&456), map.get("foo"));
+  }
+
+  #[test]
+  fn map_define_reporting() {
+    let mut map = ScopeMap::new();
+    assert_eq!(None, map.define_reporting("foo", 123));
+    map.push_layer();
+    map.push_layer();
+    assert_eq!(Some(ShadowInfo { height: 0, value: &123 }), map.define_reporting("foo", 456));
+    assert_eq!(Some(ShadowInfo { height: 0, value: &123 }), map.define_reporting("foo", 789));
+    assert_eq!(Some(&789), map.get("foo"));
+  }
+
+  #[test]
+  fn map_shadowed_in_top() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.define("bar", 2);
+    map.push_layer();
+    map.define("foo", 3);
+    map.define("baz", 4);
+
+    let shadowed: Vec<(&str, usize, i32)> = map
+      .shadowed_in_top()
+      .map(|(key, info)| (*key, info.height, *info.value))
+      .collect();
+    assert_eq!(vec![("foo", 0, 1)], shadowed);
+  }
+
+  #[test]
+  fn map_pop_layer_reporting() {
+    let mut map = ScopeMap::new();
+    map.set_read_tracking(true);
+    map.define("foo", 1);
+    map.push_layer();
+    map.define("foo", 2);
+    map.define("bar", 3);
+    map.define("baz", 4);
+    assert_eq!(Some(&2), map.get("foo"));
+    assert!(map.contains_key("bar"));
+    assert_eq!(Some(vec![&"baz"]), map.pop_layer_reporting());
+    assert_eq!(None, map.pop_layer_reporting());
+    map.set_read_tracking(false);
+    map.push_layer();
+    map.define("qux", 4);
+    assert_eq!(Some(vec![]), map.pop_layer_reporting());
+  }
+
+  #[test]
+  fn map_pop_layer_reporting_parent_read() {
+    let mut map = ScopeMap::new();
+    map.set_read_tracking(true);
+    map.push_layer();
+    map.define("foo", 1);
+    map.push_layer();
+    map.define("foo", 2);
+    assert_eq!(Some(&1), map.get_parent("foo", 1));
+    assert_eq!(Some(vec![&"foo"]), map.pop_layer_reporting());
+    assert_eq!(Some(vec![]), map.pop_layer_reporting());
+  }
+
+  #[test]
+  fn map_pop_layer_reporting_redefined() {
+    let mut map = ScopeMap::new();
+    map.set_read_tracking(true);
+    map.push_layer();
+    map.define("foo", 1);
+    map.define("bar", 2);
+    assert!(map.contains_key("foo"));
+    assert!(map.contains_key("bar"));
+    map.remove("foo");
+    map.define("foo", 3);
+    map.define("bar", 4);
+    let copy = map.clone();
+    assert_eq!(Some(&3), map.get("foo"));
+    assert_eq!(Some(vec![&"bar"]), map.pop_layer_reporting());
+    map.set_read_tracking(false);
+    assert!(!map.is_tracking_reads());
+    assert_eq!(Some(&3), copy.get("foo"));
+    assert!(copy.is_tracking_reads());
+  }
+
+  #[test]
+  fn map_scope_guard() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    {
+      let mut scope = map.scope();
+      scope.define("foo", 2);
+      scope.push_layer();
+      assert_eq!(3, scope.depth());
+      assert_eq!(Some(&2), scope.get("foo"));
+    }
+    assert_eq!(1, map.depth());
+    assert_eq!(Some(&1), map.get("foo"));
+  }
+
+  #[test]
+  fn map_with_layer() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    let inner = map.with_layer(|map| {
+      map.define("foo", 2);
+      map.get("foo").copied()
+    });
+    assert_eq!(Some(2), inner);
+    assert_eq!(1, map.depth());
+    assert_eq!(Some(&1), map.get("foo"));
+  }
+
+  #[test]
+  fn map_with_layer_panic() {
+    let mut map = ScopeMap::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+      map.with_layer(|map| {
+        map.define("foo", 1);
+        panic!("unwind");
+      })
+    }));
+    assert!(result.is_err());
+    assert_eq!(1, map.depth());
+    assert!(!map.contains_key("foo"));
+  }
+
+  #[test]
+  fn map_with_layer_meta() {
+    #[derive(Debug, PartialEq)]
+    enum Kind {
+      Function(&'static str),
+      Loop,
     }
+
+    let mut map: ScopeMap<&str, i32> = ScopeMap::new();
+    map.set_layer_meta(Kind::Function("main"));
+    let found = map.with_layer_meta(Kind::Loop, |map| {
+      map.push_layer();
+      assert_eq!(None, map.layer_meta::<Kind>(0));
+      assert_eq!(Some(&Kind::Loop), map.layer_meta(1));
+      assert_eq!(None, map.layer_meta::<u32>(1));
+      assert_eq!(None, map.layer_meta::<Kind>(usize::MAX));
+      map.push_layer_with_meta(Kind::Function("inner"));
+      map.find_layer_meta::<Kind>().map(|(kind, depth)| (format!("{:?}", kind), depth))
+    });
+    assert_eq!(Some(("Function(\"inner\")".to_owned(), 0)), found);
+    assert_eq!(1, map.depth());
+    assert_eq!(Some((&Kind::Function("main"), 0)), map.find_layer_meta());
+    map.push_layer();
+    map.pop_layer();
+    assert_eq!(Some(&Kind::Function("main")), map.layer_meta(0));
+    map.clear_all();
+    assert_eq!(None, map.layer_meta::<Kind>(0));
   }
-  
-  /// Removes all elements and additional layers.
-  #[inline]
-  pub fn clear_all(&mut self) {
-    self.map.clear();
-    self.layers.clear();
-    self.layers.push(Default::default());
-    self.empty_key_count = 0;
+
+  #[test]
+  fn map_pop_layer_checked() {
+    let mut map: ScopeMap<&str, i32> = ScopeMap::new();
+    let outer = map.push_layer_id();
+    let inner = map.push_layer_id();
+    assert_eq!(Err(PopLayerError::Mismatch { expected: outer, top: inner }), map.pop_layer_checked(outer));
+    assert_eq!(3, map.depth());
+    assert_eq!(Ok(()), map.pop_layer_checked(inner));
+    assert_eq!(Ok(()), map.pop_layer_checked(outer));
+    assert_eq!(Err(PopLayerError::BaseLayer), map.pop_layer_checked(map.top_layer_id()));
   }
 
-  /// Iterates over all key-value pairs in arbitrary order.
-  ///
-  /// The iterator element type is `(&'a K, &'a V)`.
-  #[inline]
-  pub fn iter(&self) -> impl Iterator<Item = (&'_ K, &'_ V)> {
-    self.map
-      .iter()
-      .filter_map(|(key, stack)| stack.last().map(|var| (key, &var.value)))
+  #[test]
+  fn map_layer_id_generation() {
+    let mut map: ScopeMap<&str, i32> = ScopeMap::new();
+    let first = map.push_layer_id();
+    map.pop_layer();
+    let second = map.push_layer_id();
+    assert_eq!(first.height(), second.height());
+    assert_ne!(first, second);
+    assert!(!map.has_layer(first));
+    assert!(map.has_layer(second));
   }
 
-  /// Iterates over all key-value pairs in the topmost layer in arbitrary order.
-  ///
-  /// The iterator element type is `(&'a K, &'a V)`.
-  #[inline]
-  pub fn iter_top(&self) -> impl Iterator<Item = (&'_ K, &'_ V)> {
-    self.layers
-      .last()
-      .unwrap()
-      .iter()
-      .filter_map(move |i| self.map
-        .get_index(*i)
-        .map(|(key, stack)| (key, &stack.last().unwrap().value))
-      )
+  #[test]
+  fn map_binding_handle() {
+    let mut map = ScopeMap::new();
+    let outer = map.define_with_handle("foo", 1);
+    map.push_layer();
+    let inner = map.define_with_handle("foo", 2);
+    assert_eq!(Some(&1), map.get_by_handle(outer));
+    assert_eq!(Some(&2), map.get_by_handle(inner));
+    assert_eq!(Some(inner), map.handle_of("foo"));
+    *map.get_mut_by_handle(inner).unwrap() = 3;
+    assert_eq!(Some(&3), map.get("foo"));
+    map.pop_layer();
+    assert_eq!(None, map.get_by_handle(inner));
+    map.push_layer();
+    map.define("foo", 4);
+    assert_eq!(None, map.get_by_handle(inner));
+    assert_eq!(Some(&1), map.get_by_handle(outer));
+    assert_eq!(Some(4), map.remove("foo"));
+    map.clear_all();
+    map.define("foo", 5);
+    assert_eq!(None, map.get_by_handle(outer));
   }
 
-  /// Iterates over all key-value pairs in arbitrary order, allowing mutation of the values.
-  ///
-  /// The iterator element type is `(&'a K, &'a mut V)`.
-  #[inline]
-  pub fn iter_mut(&mut self) -> impl Iterator<Item = (&'_ K, &'_ mut V)> {
-    self.map
-      .iter_mut()
-      .filter_map(|(key, stack)| stack.last_mut().map(|var| (key, &mut var.value)))
+  #[test]
+  fn map_binding_handle_stale_after_redefine() {
+    let mut map = ScopeMap::new();
+    map.push_layer();
+    let removed = map.define_with_handle("foo", 1);
+    let replaced = map.define_with_handle("bar", 2);
+    map.define("bar", 3);
+    assert_eq!(Some(&3), map.get_by_handle(replaced));
+    assert_eq!(Some(1), map.remove("foo"));
+    map.define("foo", 4);
+    assert_eq!(None, map.get_by_handle(removed));
+    assert_eq!(None, map.get_mut_by_handle(removed));
+    let cleared = map.handle_of("foo").unwrap();
+    map.clear_top();
+    map.define("foo", 5);
+    map.define("bar", 6);
+    assert_eq!(None, map.get_by_handle(cleared));
+    assert_eq!(None, map.get_by_handle(replaced));
+    assert_eq!(Some(&5), map.get_by_handle(map.handle_of("foo").unwrap()));
   }
 
-  /// Iterates over all keys in arbitrary order.
-  ///
-  /// The iterator element type is `&'a K`.
-  #[inline]
-  pub fn keys(&self) -> impl Iterator<Item = &'_ K> {
-    self.map
-      .iter()
-      .filter(|(_, stack)| !stack.is_empty())
-      .map(|(key, _)| key)
+  #[test]
+  fn map_binding_handle_after_define_parent() {
+    let mut map = ScopeMap::new();
+    map.push_layer();
+    let top = map.define_with_handle("foo", 2);
+    let bottom = map.define_parent_with_handle("foo", 1, 1);
+    assert_eq!(Some(&2), map.get_by_handle(top));
+    assert_eq!(Some(&1), map.get_by_handle(bottom));
+    assert_eq!(Some(2), map.remove("foo"));
+    assert_eq!(None, map.get_by_handle(top));
+    assert_eq!(Some(&1), map.get_by_handle(bottom));
   }
 
-  /// Iterates over all keys in the topmost layer in arbitrary order.
-  ///
-  /// The iterator element type is `&'a K`.
-  #[inline]
-  pub fn keys_top(&self) -> impl Iterator<Item = &'_ K> {
-    self.layers
-      .last()
-      .unwrap()
-      .iter()
-      .map(move |i| self.map.get_index(*i).unwrap().0)
+  #[test]
+  fn map_resolve_address() {
+    let slot_of = |address: Option<Address>| address.map(|address| (address.depth, address.slot));
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.define("bar", 2);
+    map.push_layer();
+    map.define("baz", 3);
+    map.define("foo", 4);
+    map.define("baz", 5);
+    assert_eq!(Some((0, 1)), slot_of(map.resolve("foo")));
+    assert_eq!(Some((0, 0)), slot_of(map.resolve("baz")));
+    assert_eq!(Some((1, 1)), slot_of(map.resolve("bar")));
+    assert_eq!(None, map.resolve("qux"));
+    assert_eq!(2, map.slot_count(0));
+    assert_eq!(2, map.slot_count(1));
+    assert_eq!(0, map.slot_count(2));
+    let bar = map.resolve("bar").unwrap();
+    let baz = map.resolve("baz").unwrap();
+    let foo = map.resolve("foo").unwrap();
+    assert_eq!(Some(&2), map.get_by_address(bar));
+    assert_eq!(Some(&5), map.get_by_address(baz));
+    *map.get_mut_by_address(foo).unwrap() = 6;
+    assert_eq!(Some(&6), map.get("foo"));
+    map.remove("baz");
+    assert_eq!(Some(foo), map.resolve("foo"));
+    assert_eq!(None, map.get_by_address(baz));
+    assert_eq!(2, map.slot_count(0));
+    // The vacated slot is reused, but addresses of the removed binding stay dead
+    map.define("baz", 7);
+    assert_eq!(Some((0, 0)), slot_of(map.resolve("baz")));
+    assert_eq!(None, map.get_by_address(baz));
+    assert_eq!(None, map.get_mut_by_address(baz));
+    assert_eq!(Some(&7), map.get_by_address(map.resolve("baz").unwrap()));
+    assert_eq!(2, map.slot_count(0));
+    map.define("qux", 8);
+    assert_eq!(Some((0, 2)), slot_of(map.resolve("qux")));
+    assert_eq!(3, map.slot_count(0));
+    map.clear_top();
+    assert_eq!(0, map.slot_count(0));
+    map.define("foo", 9);
+    assert_eq!(Some((0, 0)), slot_of(map.resolve("foo")));
+    assert_eq!(None, map.get_by_address(foo));
+    assert_eq!(1, map.slot_count(0));
+    assert_eq!(Some(&2), map.get_by_address(bar));
+    map.push_layer();
+    assert_eq!(None, map.get_by_address(bar));
+    assert_eq!(0, map.slot_count(usize::MAX));
+    assert_eq!(Ok(()), map.check_invariants());
   }
-}
 
-#[cfg(test)]
-mod test {
-  use super::*;
+  #[test]
+  fn map_captures_across() {
+    let mut map = ScopeMap::new();
+    map.define("global", 1);
+    map.push_layer();
+    map.define("outer", 2);
+    map.define("shadowed", 3);
+    let barrier = map.depth();
+    map.push_layer();
+    map.define("local", 4);
+    map.define("shadowed", 5);
+    let captures = map.captures_across(barrier, ["global", "outer", "shadowed", "local", "missing", "outer"]);
+    assert_eq!(vec![(&"global", 0), (&"outer", 1)], captures);
+  }
 
   #[test]
-  fn map_init() {
-    let map: ScopeMap<String, i32> = ScopeMap::new();
-    assert_eq!(0, map.len());
-    assert_eq!(1, map.depth());
-    assert!(map.is_empty());
+  fn map_capture() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.define("bar", 2);
+    map.push_layer();
+    map.define("foo", 3);
+    let captured = map.capture(["foo", "baz"]);
+    assert_eq!(1, captured.depth());
+    assert_eq!(1, captured.len());
+    assert_eq!(Some(&3), captured.get("foo"));
+    assert_eq!(Some(1), captured.get_all("foo").map(|values| values.count()));
+    assert!(!captured.contains_key("bar"));
   }
 
   #[test]
-  fn map_default() {
-    let map: ScopeMap<String, i32> = Default::default();
-    assert_eq!(0, map.len());
-    assert_eq!(1, map.depth());
-    assert!(map.is_empty());
+  fn map_inline_capacity() {
+    let mut map: ScopeMap<&str, i32, RandomState, 4, 8> = Default::default();
+    for i in 0..10 {
+      map.define("foo", i);
+      map.push_layer();
+    }
+    map.define_parent("bar", -1, 5);
+    assert_eq!(11, map.depth());
+    assert_eq!(Some(10), map.get_all("foo").map(|values| values.count()));
+    assert_eq!(Some((&-1, 5)), map.get_parent_depth("bar", 0));
+    map.check_invariants().unwrap();
+    while map.pop_layer() {}
+    assert_eq!(Some(&0), map.get("foo"));
+    assert!(!map.contains_key("bar"));
   }
 
   #[test]
-  fn map_capacity() {
-    let map: ScopeMap<String, i32> = ScopeMap::with_capacity(32);
-    assert_eq!(32, map.capacity());
+  fn map_inline_capacity_apis() {
+    let mut map: ScopeMap<&str, i32, RandomState, 4, 8> = ScopeMap::configured_with_capacity(16);
+    assert!(map.capacity() >= 16);
+    map.define("foo", 1);
+    map.with_layer(|map| {
+      map.define("foo", 2);
+      let mut cursor = map.stack_cursor_mut("foo").unwrap();
+      cursor.move_down();
+      assert_eq!(Some((0, &1)), cursor.current());
+    });
+    {
+      let mut guard = map.scope();
+      guard.define("bar", 3);
+      assert!(guard.display_layers().to_string().contains("\"bar\""));
+      assert!(guard.to_dot().contains("subgraph cluster_1 {"));
+    }
+    assert!(!map.contains_key("bar"));
+
+    let parent = Arc::new(map.clone());
+    let mut fork = parent.fork();
+    fork.define("foo", 4);
+    assert_eq!(Some(&4), fork.get("foo"));
+    let mut child = ScopeMap::with_parent(parent);
+    child.define("bar", 5);
+    assert_eq!(Some(&1), child.get("foo"));
+
+    let mut observed = map.observe(EventLog::default());
+    observed.push_layer();
+    observed.define("foo", 6);
+    assert_eq!(vec!["push 1", "define foo = 6 @ 1", "shadow foo @ 1 over 0"], observed.observer().0);
   }
 
   #[test]
-  fn map_define() {
+  fn map_reserve_and_shrink() {
     let mut map = ScopeMap::new();
-    map.define("foo", 123);
-    assert_eq!(1, map.len());
-    assert_eq!(Some(&123), map.get("foo"));
+    map.reserve(64);
+    map.reserve_layers(8);
+    map.reserve_in_top(32);
+    let stats = map.memory_stats();
+    assert!(stats.key_capacity >= 64);
+    assert!(stats.layer_capacity >= 9);
+    assert!(stats.layer_set_capacity >= 32);
+    map.define("foo", 1);
+    map.define("bar", 2);
+    map.push_layer();
+    map.define("foo", 3);
+    let stats = map.memory_stats();
+    assert_eq!(2, stats.key_count);
+    assert_eq!(3, stats.stack_entries);
+    assert_eq!(1, stats.spilled_stacks);
+    assert_eq!(2, stats.layer_count);
+    assert_eq!(3, stats.layer_slot_count);
+    map.remove("foo");
+    map.define("baz", 4);
+    assert_eq!(3, map.memory_stats().layer_slot_count);
+    map.pop_layer();
+    map.shrink_to_fit();
+    let stats = map.memory_stats();
+    assert_eq!(0, stats.spilled_stacks);
+    assert!(stats.key_capacity < 64);
+    assert_eq!(Some(&1), map.get("foo"));
+    map.check_invariants().unwrap();
   }
 
   #[test]
-  fn map_define_parent() {
+  fn map_shrink_removes_empty_keys() {
     let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.define("bar", 2);
     map.push_layer();
-    map.define_parent("foo", 123, 1);
-    assert_eq!(Some(1), map.depth_of("foo"));
-    assert_eq!(Some(&123), map.get_parent("foo", 1));
-    assert_eq!(None, map.get_parent("foo", 2));
+    map.define("baz", 3);
+    map.define("qux", 4);
+    map.define("bar", 5);
+    map.remove("baz");
+    let address = map.resolve("qux").unwrap();
+    map.push_layer();
+    map.define("tmp", 6);
+    map.pop_layer();
+    assert_eq!(5, map.memory_stats().key_count);
+    map.shrink_to_fit();
+    let stats = map.memory_stats();
+    assert_eq!(3, stats.key_count);
+    assert_eq!(4, stats.stack_entries);
+    assert_eq!(3, map.len());
+    assert_eq!(vec![&"foo", &"bar", &"qux"], map.keys().collect::<Vec<_>>());
+    assert_eq!(Some(&4), map.get_by_address(address));
+    assert_eq!(Some(&2), map.get_parent("bar", 1));
+    map.define("tmp", 7);
+    assert_eq!(Some(&7), map.get("tmp"));
+    map.check_invariants().unwrap();
+    map.set_read_tracking(true);
+    map.push_layer();
+    map.define("foo", 8);
+    map.define("qux", 9);
+    map.push_layer();
+    map.define("quux", 10);
+    map.pop_layer();
+    assert_eq!(Some(&9), map.get("qux"));
+    map.shrink_to_fit();
+    assert_eq!(Some(vec![&"foo"]), map.pop_layer_reporting());
   }
 
   #[test]
-  fn map_define_parent_after_child() {
+  fn map_get_key_value() {
     let mut map = ScopeMap::new();
+    map.define(String::from("foo"), 1);
     map.push_layer();
-    map.define("foo", 456);
-    map.define_parent("foo", 123, 1);
-    assert_eq!(Some(&456), map.get("foo"));
-    assert_eq!(Some(&123), map.get_parent("foo", 1));
+    map.define(String::from("foo"), 2);
+    assert_eq!(Some((&String::from("foo"), &2)), map.get_key_value("foo"));
+    assert_eq!(None, map.get_key_value("bar"));
+  }
+
+  #[test]
+  fn map_define_many() {
+    let mut map = ScopeMap::new();
+    map.define_many(vec![("foo", 1), ("bar", 2)]);
+    map.push_layer_from(vec![("foo", 3), ("baz", 4), ("foo", 5)]);
+    assert_eq!(2, map.depth());
+    assert_eq!(3, map.len());
+    assert_eq!(Some(&5), map.get("foo"));
+    assert_eq!(Some(&1), map.get_parent("foo", 1));
+    assert_eq!(Some((0, 0)), map.resolve("foo").map(|address| (address.depth, address.slot)));
+    assert_eq!(2, map.slot_count(0));
     map.pop_layer();
-    assert_eq!(Some(&123), map.get("foo"));
+    assert_eq!(Some(&1), map.get("foo"));
+    assert!(!map.contains_key("baz"));
+    map.check_invariants().unwrap();
   }
 
   #[test]
-  fn map_define_parent_saturated() {
+  fn map_define_in() {
     let mut map = ScopeMap::new();
+    let outer = map.push_layer_id();
     map.push_layer();
-    map.define_parent("foo", 123, 3);
-    assert_eq!(Some(1), map.depth_of("foo"));
-    assert_eq!(Some(&123), map.get("foo"));
+    map.define("foo", 2);
+    assert!(map.define_in(outer, "foo", 1));
+    assert_eq!(Some((&1, 1)), map.get_parent_height("foo", 1));
+    map.pop_layer();
+    assert_eq!(Some(&1), map.get("foo"));
+    map.pop_layer();
+    assert!(!map.define_in(outer, "foo", 3));
+    assert!(!map.contains_key("foo"));
   }
 
   #[test]
@@ -684,6 +2479,7 @@ mod test {
   }
 
   #[test]
+  #[allow(clippy::bool_assert_comparison)]
   fn map_try_pop_first_layer() {
     let mut map: ScopeMap<String, i32> = Default::default();
     assert_eq!(false, map.pop_layer());
@@ -749,6 +2545,50 @@ mod test {
     assert_eq!(Some((&123, 0)), map.get_parent_height("foo", 2));
   }
 
+  #[test]
+  fn map_get_at_depth() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.push_layer();
+    map.push_layer();
+    map.define("foo", 3);
+    assert_eq!(Some(&3), map.get_at_depth("foo", 0));
+    assert_eq!(None, map.get_at_depth("foo", 1));
+    assert_eq!(Some(&1), map.get_at_depth("foo", 2));
+    assert_eq!(None, map.get_at_depth("foo", 3));
+    assert_eq!(None, map.get_at_depth("foo", usize::MAX));
+  }
+
+  #[test]
+  fn map_get_at_height() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.push_layer();
+    map.push_layer();
+    map.define("foo", 3);
+    assert_eq!(Some(&1), map.get_at_height("foo", 0));
+    assert_eq!(None, map.get_at_height("foo", 1));
+    assert_eq!(Some(&3), map.get_at_height("foo", 2));
+    if let Some(foo) = map.get_at_height_mut("foo", 0) {
+      *foo = 10;
+    }
+    map.pop_layer();
+    assert_eq!(Some(&10), map.get("foo"));
+  }
+
+  #[test]
+  fn map_contains_key_at_depth() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.push_layer();
+    map.define("bar", 2);
+    assert!(map.contains_key_at_depth("foo", 1));
+    assert!(!map.contains_key_at_depth("foo", 0));
+    assert!(!map.contains_key_at_depth("foo", usize::MAX));
+    assert!(map.contains_key_at_height("bar", 1));
+    assert!(!map.contains_key_at_height("bar", 0));
+  }
+
   #[test]
   fn map_get_all() {
     let mut map = ScopeMap::new();
@@ -773,6 +2613,205 @@ mod test {
     assert_eq!(Some(vec![2, 1]), values);
   }
 
+  #[test]
+  fn map_stack_cursor() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.push_layer();
+    map.push_layer();
+    map.define("foo", 3);
+
+    let mut cursor = map.stack_cursor("foo").unwrap();
+    assert_eq!(&"foo", cursor.key());
+    assert_eq!(Some((2, &3)), cursor.current());
+    assert_eq!(Some((0, &1)), cursor.peek_down());
+    cursor.move_down();
+    assert_eq!(Some((0, &1)), cursor.current());
+    cursor.move_down();
+    assert_eq!(None, cursor.current());
+    cursor.move_down();
+    assert_eq!(Some((2, &3)), cursor.current());
+    assert_eq!(vec![(2, &3), (0, &1)], cursor.collect::<Vec<_>>());
+    assert!(map.stack_cursor("bar").is_none());
+  }
+
+  #[test]
+  fn map_stack_cursor_mut_replace() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.push_layer();
+    map.define("foo", 2);
+
+    let mut cursor = map.stack_cursor_mut("foo").unwrap();
+    cursor.move_down();
+    assert_eq!(Some(1), cursor.replace(10));
+    if let Some((_, value)) = cursor.current_mut() {
+      *value += 1;
+    }
+    map.pop_layer();
+    assert_eq!(Some(&11), map.get("foo"));
+  }
+
+  #[test]
+  fn map_stack_cursor_mut_remove() {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.push_layer();
+    map.define("foo", 2);
+
+    let mut cursor = map.stack_cursor_mut("foo").unwrap();
+    cursor.move_down();
+    assert_eq!(Some((0, 1)), cursor.remove_current());
+    assert_eq!(None, cursor.current());
+    cursor.move_down();
+    assert_eq!(Some((1, 2)), cursor.remove_current());
+    assert!(!map.contains_key("foo"));
+    assert_eq!(0, map.len());
+    map.define("foo", 3);
+    assert_eq!(1, map.len());
+    assert_eq!(Some(1), map.height_of("foo"));
+    assert!(map.contains_key_at_top("foo"));
+    map.pop_layer();
+    assert!(!map.contains_key("foo"));
+  }
+
+  #[derive(Default)]
+  struct EventLog(Vec<String>);
+
+  impl ScopeObserver<&'static str, i32> for EventLog {
+    fn on_define(&mut self, key: &&'static str, value: &i32, height: usize) {
+      self.0.push(format!("define {} = {} @ {}", key, value, height));
+    }
+
+    fn on_shadow(&mut self, key: &&'static str, height: usize, shadowed_height: usize) {
+      self.0.push(format!("shadow {} @ {} over {}", key, height, shadowed_height));
+    }
+
+    fn on_get_mut(&mut self, key: &&'static str, height: usize) {
+      self.0.push(format!("get_mut {} @ {}", key, height));
+    }
+
+    fn on_remove(&mut self, key: &&'static str, value: &i32, height: usize) {
+      self.0.push(format!("remove {} = {} @ {}", key, value, height));
+    }
+
+    fn on_push_layer(&mut self, height: usize) {
+      self.0.push(format!("push {}", height));
+    }
+
+    fn on_pop_layer(&mut self, height: usize, removed_keys: &[&&'static str]) {
+      self.0.push(format!("pop {} {:?}", height, removed_keys));
+    }
+  }
+
+  #[test]
+  fn map_observe() {
+    let mut map = ScopeMap::new().observe(EventLog::default());
+    map.define("foo", 1);
+    map.push_layer();
+    map.define("foo", 2);
+    map.define("bar", 3);
+    assert_eq!(Some(3), map.remove("bar"));
+    assert_eq!(None, map.remove("bar"));
+    assert_eq!(Some(&2), map.get("foo"));
+    map.pop_layer();
+    assert_eq!(
+      vec![
+        "define foo = 1 @ 0",
+        "push 1",
+        "define foo = 2 @ 1",
+        "shadow foo @ 1 over 0",
+        "define bar = 3 @ 1",
+        "remove bar = 3 @ 1",
+        "pop 1 [\"foo\"]",
+      ],
+      map.observer().0
+    );
+  }
+
+  #[test]
+  fn map_observe_mutations() {
+    let mut map = ScopeMap::new().observe(EventLog::default());
+    map.set_redefinition_policy(RedefinitionPolicy::Reject);
+    map.define("foo", 1);
+    let layer = map.push_layer_id();
+    assert!(map.try_define("foo", 2).is_ok());
+    assert!(map.try_define("foo", 3).is_err());
+    *map.get_mut("foo").unwrap() += 10;
+    *map.get_parent_mut("foo", 1).unwrap() += 20;
+    assert_eq!(None, map.get_mut("bar"));
+    assert_eq!(Some(0), map.define_reporting("foo", 4).map(|info| info.height));
+    map.define_many(vec![("bar", 5)]);
+    assert!(map.define_in(layer, "baz", 6));
+    assert_eq!(Some(&21), map.get_parent("foo", 1));
+    assert_eq!(Ok(()), map.pop_layer_checked(layer));
+    map.push_layer_from(vec![("qux", 7)]);
+    map.clear_all();
+    assert_eq!(0, map.len());
+    assert_eq!(
+      vec![
+        "define foo = 1 @ 0",
+        "push 1",
+        "define foo = 2 @ 1",
+        "shadow foo @ 1 over 0",
+        "get_mut foo @ 1",
+        "get_mut foo @ 0",
+        "define foo = 4 @ 1",
+        "shadow foo @ 1 over 0",
+        "define bar = 5 @ 1",
+        "define baz = 6 @ 1",
+        "pop 1 [\"foo\", \"bar\", \"baz\"]",
+        "push 1",
+        "define qux = 7 @ 1",
+        "pop 1 [\"qux\"]",
+        "remove foo = 21 @ 0",
+      ],
+      map.observer().0
+    );
+  }
+
+  #[test]
+  fn map_observe_handles() {
+    let mut map = ScopeMap::new().observe(EventLog::default());
+    map.define("foo", 1);
+    map.push_layer_with_meta("block");
+    map.set_layer_meta("loop");
+    map.reserve(4);
+    let handle = map.define_with_handle("foo", 2);
+    let parent = map.define_parent_with_handle("bar", 3, 1);
+    *map.get_mut_by_handle(handle).unwrap() += 10;
+    *map.get_mut_by_handle(parent).unwrap() += 10;
+    let address = map.resolve("bar").unwrap();
+    *map.get_mut_by_address(address).unwrap() += 10;
+    assert_eq!(2, map.get_all_mut("foo").unwrap().count());
+    assert_eq!(1, map.get_parents_mut("foo", 1).unwrap().count());
+    for (_, value) in map.iter_mut() {
+      *value += 1;
+    }
+    map.shrink_to_fit();
+    assert_eq!(Some(&"loop"), map.layer_meta::<&str>(0));
+    assert_eq!(Some(&13), map.get("foo"));
+    assert_eq!(Some(&24), map.get("bar"));
+    assert_eq!(
+      vec![
+        "define foo = 1 @ 0",
+        "push 1",
+        "define foo = 2 @ 1",
+        "shadow foo @ 1 over 0",
+        "define bar = 3 @ 0",
+        "get_mut foo @ 1",
+        "get_mut bar @ 0",
+        "get_mut bar @ 0",
+        "get_mut foo @ 1",
+        "get_mut foo @ 0",
+        "get_mut foo @ 0",
+        "get_mut foo @ 1",
+        "get_mut bar @ 0",
+      ],
+      map.observer().0
+    );
+  }
+
   #[test]
   fn map_define_override() {
     let mut map = ScopeMap::new();
@@ -0,0 +1,317 @@
+use std::{borrow::Borrow, collections::hash_map::RandomState, hash::{BuildHasher, Hash, Hasher}};
+
+use indexmap::Equivalent;
+
+use crate::ScopeMap;
+
+/// A layered hash map with several namespaces that share a single layer stack.
+///
+/// Languages often keep separate namespaces (e.g. for types, values and macros) that follow the same block structure.
+/// Each key is qualified by a namespace of type `N`, so the same key can be bound independently in every namespace,
+/// while `push_layer()` and `pop_layer()` affect all namespaces at once.
+#[derive(Clone)]
+pub struct NamespacedScopeMap<N, K, V, S: BuildHasher = RandomState, const STACK: usize = 1, const LAYERS: usize = 1> {
+  map: ScopeMap<(N, K), V, S, STACK, LAYERS>,
+}
+
+/// A borrowed namespace-qualified key, used to query the underlying map without an owned key.
+struct NsKey<'a, N, Q: ?Sized>(N, &'a Q);
+
+impl<'a, N: Hash, Q: ?Sized + Hash> Hash for NsKey<'a, N, Q> {
+  /// Hashes the same way as the tuple `(N, K)`.
+  #[inline]
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.0.hash(state);
+    self.1.hash(state);
+  }
+}
+
+impl<'a, N, K, Q> Equivalent<(N, K)> for NsKey<'a, N, Q>
+where
+  N: Eq,
+  K: Borrow<Q>,
+  Q: ?Sized + Eq,
+{
+  #[inline]
+  fn equivalent(&self, key: &(N, K)) -> bool {
+    self.0 == key.0 && *self.1 == *key.1.borrow()
+  }
+}
+
+impl<N, K, V, S: Default + BuildHasher, const STACK: usize, const LAYERS: usize> Default for NamespacedScopeMap<N, K, V, S, STACK, LAYERS> {
+  /// Creates a new `NamespacedScopeMap` with the default configuration.
+  #[inline]
+  fn default() -> Self {
+    Self {
+      map: Default::default()
+    }
+  }
+}
+
+impl<N, K, V> NamespacedScopeMap<N, K, V, RandomState> {
+  /// Creates an empty `NamespacedScopeMap` with a default hasher and capacity.
+  #[inline]
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Creates an empty `NamespacedScopeMap` with a default hasher and the specified capacity.
+  #[inline]
+  pub fn with_capacity(capacity: usize) -> Self {
+    Self {
+      map: ScopeMap::with_capacity(capacity)
+    }
+  }
+}
+
+impl<N, K, V, S: BuildHasher> NamespacedScopeMap<N, K, V, S> {
+  /// Creates an empty `NamespacedScopeMap` with the specified hasher and a default capacity.
+  #[inline]
+  pub fn with_hasher(hash_builder: S) -> Self {
+    Self {
+      map: ScopeMap::with_hasher(hash_builder)
+    }
+  }
+}
+
+impl<N, K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> NamespacedScopeMap<N, K, V, S, STACK, LAYERS> {
+  /// Creates an empty `NamespacedScopeMap` with the specified hasher and a default capacity, using the inline capacities given by the type.
+  ///
+  /// Unlike [`NamespacedScopeMap::with_hasher`], this works for any `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn configured_with_hasher(hash_builder: S) -> Self {
+    Self {
+      map: ScopeMap::configured_with_hasher(hash_builder)
+    }
+  }
+
+  /// Gets the number of elements the map can hold without reallocating.
+  #[inline]
+  pub fn capacity(&self) -> usize {
+    self.map.capacity()
+  }
+
+  /// Returns `true` if the map is empty.
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.map.is_empty()
+  }
+
+  /// Gets the number of unique keys in the map, across all namespaces.
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.map.len()
+  }
+
+  /// Gets the number of layers in the map.
+  #[inline]
+  pub fn depth(&self) -> usize {
+    self.map.depth()
+  }
+
+  /// Adds a new, empty layer to all namespaces.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn push_layer(&mut self) {
+    self.map.push_layer()
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys/values in every namespace.
+  /// Returns `true` if a layer was removed.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys stored in the removed layer.
+  #[inline]
+  pub fn pop_layer(&mut self) -> bool {
+    self.map.pop_layer()
+  }
+}
+
+impl<N: Copy + Eq + Hash, K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> NamespacedScopeMap<N, K, V, S, STACK, LAYERS> {
+  /// Adds the specified entry to the topmost layer of namespace `ns`.
+  #[inline]
+  pub fn define(&mut self, ns: N, key: K, value: V) {
+    self.map.define((ns, key), value)
+  }
+
+  /// Adds the specified entry to namespace `ns` in the layer `min_depth` layers from the top. Saturates to base layer.
+  #[inline]
+  pub fn define_parent(&mut self, ns: N, key: K, value: V, min_depth: usize) {
+    self.map.define_parent((ns, key), value, min_depth)
+  }
+
+  /// Removes the entry with the specified key from the topmost layer of namespace `ns` and returns its value.
+  #[inline]
+  pub fn remove<Q>(&mut self, ns: N, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.remove_equivalent(&NsKey(ns, key))
+  }
+
+  /// Removes all entries in the topmost layer of every namespace.
+  #[inline]
+  pub fn clear_top(&mut self) {
+    self.map.clear_top()
+  }
+
+  /// Removes all elements and additional layers.
+  #[inline]
+  pub fn clear_all(&mut self) {
+    self.map.clear_all()
+  }
+
+  /// Returns `true` if namespace `ns` contains the specified key in any layer.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn contains_key<Q>(&self, ns: N, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.contains_key_equivalent(&NsKey(ns, key))
+  }
+
+  /// Returns `true` if namespace `ns` contains the specified key at the top layer.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn contains_key_at_top<Q>(&self, ns: N, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.contains_key_at_top_equivalent(&NsKey(ns, key))
+  }
+
+  /// Gets a reference to the topmost value associated with a key in namespace `ns`.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get<Q>(&self, ns: N, key: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.get_equivalent(&NsKey(ns, key))
+  }
+
+  /// Gets a mutable reference to the topmost value associated with a key in namespace `ns`.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get_mut<Q>(&mut self, ns: N, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.get_mut_equivalent(&NsKey(ns, key))
+  }
+
+  /// Gets the depth of the specified key in namespace `ns` (i.e. how many layers down from the top that the key first appears).
+  /// A depth of 0 refers to the top layer.
+  ///
+  /// Returns `None` if the key does not exist.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn depth_of<Q>(&self, ns: N, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.height_of(ns, key).map(|height| self.depth() - height - 1)
+  }
+
+  /// Gets the height of the specified key in namespace `ns` (i.e. how many layers up from the bottom that the key last appears).
+  /// A height of 0 refers to the bottom layer.
+  ///
+  /// Returns `None` if the key does not exist.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn height_of<Q>(&self, ns: N, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.height_of_equivalent(&NsKey(ns, key))
+  }
+
+  /// Iterates over all key-value pairs in namespace `ns` in arbitrary order.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys in all namespaces.
+  ///
+  /// The iterator element type is `(&'a K, &'a V)`.
+  #[inline]
+  pub fn iter(&self, ns: N) -> impl Iterator<Item = (&'_ K, &'_ V)> {
+    self.map
+      .iter()
+      .filter(move |((key_ns, _), _)| *key_ns == ns)
+      .map(|((_, key), value)| (key, value))
+  }
+
+  /// Iterates over all key-value pairs in the topmost layer of namespace `ns` in arbitrary order.
+  ///
+  /// The iterator element type is `(&'a K, &'a V)`.
+  #[inline]
+  pub fn iter_top(&self, ns: N) -> impl Iterator<Item = (&'_ K, &'_ V)> {
+    self.map
+      .iter_top()
+      .filter(move |((key_ns, _), _)| *key_ns == ns)
+      .map(|((_, key), value)| (key, value))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+  enum Ns {
+    Type,
+    Value,
+  }
+
+  #[test]
+  fn namespaced_separate_namespaces() {
+    let mut map = NamespacedScopeMap::new();
+    map.define(Ns::Type, "foo".to_owned(), 1);
+    map.define(Ns::Value, "foo".to_owned(), 2);
+    assert_eq!(2, map.len());
+    assert_eq!(Some(&1), map.get(Ns::Type, "foo"));
+    assert_eq!(Some(&2), map.get(Ns::Value, "foo"));
+    assert_eq!(Some(2), map.remove(Ns::Value, "foo"));
+    assert!(map.contains_key(Ns::Type, "foo"));
+    assert!(!map.contains_key(Ns::Value, "foo"));
+  }
+
+  #[test]
+  fn namespaced_shared_layers() {
+    let mut map = NamespacedScopeMap::new();
+    map.define(Ns::Type, "foo", 1);
+    map.push_layer();
+    map.define(Ns::Type, "foo", 2);
+    map.define(Ns::Value, "bar", 3);
+    assert_eq!(Some(0), map.depth_of(Ns::Type, "foo"));
+    assert_eq!(Some(1), map.height_of(Ns::Value, "bar"));
+    assert_eq!(vec![(&"bar", &3)], map.iter_top(Ns::Value).collect::<Vec<_>>());
+    map.pop_layer();
+    assert_eq!(Some(&1), map.get(Ns::Type, "foo"));
+    assert!(!map.contains_key(Ns::Value, "bar"));
+    assert_eq!(vec![(&"foo", &1)], map.iter(Ns::Type).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn namespaced_configured() {
+    let mut map: NamespacedScopeMap<Ns, &str, i32, RandomState, 4, 8> = NamespacedScopeMap::configured_with_hasher(RandomState::new());
+    map.define(Ns::Type, "foo", 1);
+    map.push_layer();
+    map.define(Ns::Type, "foo", 2);
+    assert_eq!(Some(&2), map.get(Ns::Type, "foo"));
+    assert!(map.pop_layer());
+    assert_eq!(Some(&1), map.get(Ns::Type, "foo"));
+  }
+}
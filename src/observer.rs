@@ -0,0 +1,747 @@
+use std::{any::Any, borrow::Borrow, hash::{BuildHasher, Hash}, ops::Deref};
+
+use crate::{map::{find_in_stack, parent_count}, Address, BindingHandle, DefineOutcome, LayerId, PopLayerError, RedefinitionError, RedefinitionPolicy, ScopeMap, ScopeSet, ShadowInfo};
+
+/// Receives notifications about changes to an observed [`ScopeMap`] or [`ScopeSet`].
+///
+/// All methods do nothing by default, so implementors only need to override the events they care about.
+/// Attach an observer with [`ScopeMap::observe`] or [`ScopeSet::observe`].
+pub trait ScopeObserver<K, V> {
+  /// Called after `key` is defined with `value` in the layer at `height`.
+  #[inline]
+  fn on_define(&mut self, key: &K, value: &V, height: usize) {
+    let _ = (key, value, height);
+  }
+
+  /// Called after `on_define()` when the new binding at `height` hides a binding of the same key at `shadowed_height`.
+  #[inline]
+  fn on_shadow(&mut self, key: &K, height: usize, shadowed_height: usize) {
+    let _ = (key, height, shadowed_height);
+  }
+
+  /// Called before mutable access to the binding of `key` in the layer at `height` is handed out by `get_mut()` or similar.
+  ///
+  /// The value may be changed through the returned reference after this call.
+  #[inline]
+  fn on_get_mut(&mut self, key: &K, height: usize) {
+    let _ = (key, height);
+  }
+
+  /// Called when the binding of `key` with `value` is removed from the layer at `height`,
+  /// either by `remove()`, `clear_top()` or `clear_all()`.
+  #[inline]
+  fn on_remove(&mut self, key: &K, value: &V, height: usize) {
+    let _ = (key, value, height);
+  }
+
+  /// Called after a new layer is added at `height`.
+  #[inline]
+  fn on_push_layer(&mut self, height: usize) {
+    let _ = height;
+  }
+
+  /// Called after the layer at `height` is removed, along with the keys that were defined in it.
+  #[inline]
+  fn on_pop_layer(&mut self, height: usize, removed_keys: &[&K]) {
+    let _ = (height, removed_keys);
+  }
+}
+
+/// A [`ScopeMap`] that notifies a [`ScopeObserver`] of every change made through it.
+///
+/// Created by [`ScopeMap::observe`]. The wrapper dereferences to the underlying map for read access;
+/// changes must go through the wrapper's own methods so that the observer sees them.
+/// Every mutating method of the map is forwarded except `stack_cursor_mut()`, `scope()`, `with_layer()` and `with_layer_meta()`,
+/// which would let changes bypass the observer and are not available. Layer metadata and capacity changes are forwarded but not observed.
+/// Maps without an observer pay nothing for this feature.
+pub struct ObservedScopeMap<K, V, O, S: BuildHasher, const STACK: usize = 1, const LAYERS: usize = 1> {
+  map: ScopeMap<K, V, S, STACK, LAYERS>,
+  observer: O,
+}
+
+impl<K, V, O, S: BuildHasher, const STACK: usize, const LAYERS: usize> ObservedScopeMap<K, V, O, S, STACK, LAYERS> {
+  #[inline]
+  pub(crate) fn new(map: ScopeMap<K, V, S, STACK, LAYERS>, observer: O) -> Self {
+    Self {
+      map,
+      observer,
+    }
+  }
+
+  /// Gets a reference to the observer.
+  #[inline]
+  pub fn observer(&self) -> &O {
+    &self.observer
+  }
+
+  /// Gets a mutable reference to the observer.
+  #[inline]
+  pub fn observer_mut(&mut self) -> &mut O {
+    &mut self.observer
+  }
+
+  /// Detaches the observer and returns it along with the map.
+  #[inline]
+  pub fn into_inner(self) -> (ScopeMap<K, V, S, STACK, LAYERS>, O) {
+    (self.map, self.observer)
+  }
+}
+
+impl<K, V, O, S: BuildHasher, const STACK: usize, const LAYERS: usize> Deref for ObservedScopeMap<K, V, O, S, STACK, LAYERS> {
+  type Target = ScopeMap<K, V, S, STACK, LAYERS>;
+
+  #[inline]
+  fn deref(&self) -> &Self::Target {
+    &self.map
+  }
+}
+
+impl<K: Eq + Hash, V, O: ScopeObserver<K, V>, S: BuildHasher, const STACK: usize, const LAYERS: usize> ObservedScopeMap<K, V, O, S, STACK, LAYERS> {
+  /// Adds a new, empty layer.
+  #[inline]
+  pub fn push_layer(&mut self) {
+    observed_push_layer(&mut self.map, &mut self.observer)
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys/values.
+  /// Returns `true` if a layer was removed.
+  #[inline]
+  pub fn pop_layer(&mut self) -> bool {
+    observed_pop_layer(&mut self.map, &mut self.observer)
+  }
+
+  /// Adds the specified entry to the topmost layer.
+  #[inline]
+  pub fn define(&mut self, key: K, value: V) {
+    let height = self.map.depth() - 1;
+    observed_define(&mut self.map, &mut self.observer, key, value, height);
+  }
+
+  /// Adds the specified entry in the layer `min_depth` layers from the top. Saturates to base layer.
+  #[inline]
+  pub fn define_parent(&mut self, key: K, value: V, min_depth: usize) {
+    let height = (self.map.depth() - 1).saturating_sub(min_depth);
+    observed_define(&mut self.map, &mut self.observer, key, value, height);
+  }
+
+  /// Removes the entry with the specified key from the topmost layer and returns its value.
+  #[inline]
+  pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    observed_remove(&mut self.map, &mut self.observer, key)
+  }
+
+  /// Removes all entries in the topmost layer.
+  #[inline]
+  pub fn clear_top(&mut self) {
+    observed_clear_top(&mut self.map, &mut self.observer)
+  }
+
+  /// Adds a new, empty layer and returns its ID.
+  #[inline]
+  pub fn push_layer_id(&mut self) -> LayerId {
+    observed_push_layer(&mut self.map, &mut self.observer);
+    self.map.top_layer_id()
+  }
+
+  /// Removes the topmost layer if its ID matches `id`.
+  ///
+  /// Returns an error if `id` does not identify the topmost layer, or if it identifies the base layer.
+  #[inline]
+  pub fn pop_layer_checked(&mut self, id: LayerId) -> Result<(), PopLayerError> {
+    observed_pop_layer_checked(&mut self.map, &mut self.observer, id)
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys/values.
+  /// Returns the keys defined in the removed layer that were never read, or `None` if no layer was removed.
+  #[inline]
+  pub fn pop_layer_reporting(&mut self) -> Option<Vec<&K>> {
+    observed_pop_layer_reporting(&mut self.map, &mut self.observer)
+  }
+
+  /// Adds a new layer containing the specified entries.
+  #[inline]
+  pub fn push_layer_from(&mut self, entries: impl IntoIterator<Item = (K, V)>) {
+    self.push_layer();
+    self.define_many(entries);
+  }
+
+  /// Adds all of the specified entries to the topmost layer.
+  #[inline]
+  pub fn define_many(&mut self, entries: impl IntoIterator<Item = (K, V)>) {
+    for (key, value) in entries {
+      self.define(key, value);
+    }
+  }
+
+  /// Adds the specified entry to the topmost layer, applying the map's [`RedefinitionPolicy`].
+  ///
+  /// The observer is only notified if the entry is defined.
+  #[inline]
+  pub fn try_define(&mut self, key: K, value: V) -> Result<DefineOutcome<V>, RedefinitionError<K, V>> {
+    let height = self.map.depth() - 1;
+    let (stack_index, outcome) = self.map.try_define_index(key, value)?;
+    notify_define(&self.map, &mut self.observer, stack_index, height);
+    Ok(outcome)
+  }
+
+  /// Adds the specified entry to the topmost layer.
+  /// If the new binding hides a binding in a lower layer, returns the height and value of the hidden binding.
+  #[inline]
+  pub fn define_reporting(&mut self, key: K, value: V) -> Option<ShadowInfo<'_, V>> {
+    let height = self.map.depth() - 1;
+    let stack_index = observed_define(&mut self.map, &mut self.observer, key, value, height);
+    let stack = self.map.stack_at(stack_index).1;
+    stack.iter().rev().nth(1).map(|var| ShadowInfo { height: var.layer, value: &var.value })
+  }
+
+  /// Adds the specified entry to the layer identified by `id`.
+  ///
+  /// Returns `false` and discards the entry if the layer no longer exists.
+  #[inline]
+  pub fn define_in(&mut self, id: LayerId, key: K, value: V) -> bool {
+    if !self.map.has_layer(id) {
+      return false
+    }
+    observed_define(&mut self.map, &mut self.observer, key, value, id.height());
+    true
+  }
+
+  /// Gets a mutable reference to the topmost value associated with a key.
+  #[inline]
+  pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let height = self.map.height_of(key)?;
+    notify_get_mut(&self.map, &mut self.observer, key, height);
+    self.map.get_mut(key)
+  }
+
+  /// Gets a mutable reference to a value `min_depth` layers below the topmost value associated with a key.
+  /// Saturates to base layer.
+  #[inline]
+  pub fn get_parent_mut<Q>(&mut self, key: &Q, min_depth: usize) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let (_, height) = self.map.get_parent_height(key, min_depth)?;
+    notify_get_mut(&self.map, &mut self.observer, key, height);
+    self.map.get_parent_mut(key, min_depth)
+  }
+
+  /// Gets a mutable reference to the value associated with a key in the layer at exactly `height`.
+  #[inline]
+  pub fn get_at_height_mut<Q>(&mut self, key: &Q, height: usize) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    if !self.map.contains_key_at_height(key, height) {
+      return None
+    }
+    notify_get_mut(&self.map, &mut self.observer, key, height);
+    self.map.get_at_height_mut(key, height)
+  }
+
+  /// Gets an iterator over mutable references to all the values associated with a key, starting with the topmost and going down.
+  ///
+  /// The observer is notified of every binding of the key before the iterator is returned.
+  #[inline]
+  pub fn get_all_mut<Q>(&mut self, key: &Q) -> Option<impl Iterator<Item = &mut V>>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let stack_index = self.map.index_of_equivalent(key)?;
+    let (key, stack) = self.map.stack_at(stack_index);
+    for var in stack.iter().rev() {
+      self.observer.on_get_mut(key, var.layer);
+    }
+    Some(self.map.stack_at_mut(stack_index).iter_mut().rev().map(|var| &mut var.value))
+  }
+
+  /// Gets an iterator over mutable references to all values `min_depth` layers below the topmost value associated with a key.
+  /// Saturates to base layer.
+  ///
+  /// The observer is notified of every binding the iterator yields before it is returned.
+  #[inline]
+  pub fn get_parents_mut<Q>(&mut self, key: &Q, min_depth: usize) -> Option<impl Iterator<Item = &mut V>>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let stack_index = self.map.index_of_equivalent(key)?;
+    let (key, stack) = self.map.stack_at(stack_index);
+    let count = parent_count(stack, self.map.depth(), min_depth);
+    for var in stack[..count].iter().rev() {
+      self.observer.on_get_mut(key, var.layer);
+    }
+    Some(self.map.stack_at_mut(stack_index)[..count].iter_mut().rev().map(|var| &mut var.value))
+  }
+
+  /// Gets a mutable reference to the value of the binding at `address`.
+  #[inline]
+  pub fn get_mut_by_address(&mut self, address: Address) -> Option<&mut V> {
+    let (stack_index, _, height) = self.map.binding_at_address(address)?;
+    self.observer.on_get_mut(self.map.stack_at(stack_index).0, height);
+    self.map.get_mut_by_address(address)
+  }
+
+  /// Gets a mutable reference to the value of the binding referred to by `handle`.
+  ///
+  /// Returns `None` if the binding's layer has been popped or the binding has been removed.
+  #[inline]
+  pub fn get_mut_by_handle(&mut self, handle: BindingHandle) -> Option<&mut V> {
+    let position = self.map.position_of_handle(handle)?;
+    let (key, stack) = self.map.stack_at(handle.index());
+    self.observer.on_get_mut(key, stack[position].layer);
+    self.map.get_mut_by_handle(handle)
+  }
+
+  /// Iterates over all key-value pairs in arbitrary order, allowing mutation of the values.
+  ///
+  /// The observer is notified of every binding the iterator yields before it is returned.
+  #[inline]
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = (&'_ K, &'_ mut V)> {
+    for (key, height) in self.map.top_heights() {
+      self.observer.on_get_mut(key, height);
+    }
+    self.map.iter_mut()
+  }
+
+  /// Adds the specified entry to the topmost layer and returns a handle to the new binding.
+  #[inline]
+  pub fn define_with_handle(&mut self, key: K, value: V) -> BindingHandle {
+    let height = self.map.depth() - 1;
+    let stack_index = observed_define(&mut self.map, &mut self.observer, key, value, height);
+    self.map.handle_at_height(stack_index, height)
+  }
+
+  /// Adds the specified entry in the layer `min_depth` layers from the top and returns a handle to the new binding.
+  /// Saturates to base layer.
+  #[inline]
+  pub fn define_parent_with_handle(&mut self, key: K, value: V, min_depth: usize) -> BindingHandle {
+    let height = (self.map.depth() - 1).saturating_sub(min_depth);
+    let stack_index = observed_define(&mut self.map, &mut self.observer, key, value, height);
+    self.map.handle_at_height(stack_index, height)
+  }
+
+  /// Adds a new, empty layer with the specified metadata attached.
+  #[inline]
+  pub fn push_layer_with_meta<M: Any + Send + Sync>(&mut self, meta: M) {
+    observed_push_layer(&mut self.map, &mut self.observer);
+    self.map.set_layer_meta(meta);
+  }
+
+  /// Attaches metadata to the topmost layer, replacing any metadata it already has.
+  ///
+  /// Layer metadata is not observed.
+  #[inline]
+  pub fn set_layer_meta<M: Any + Send + Sync>(&mut self, meta: M) {
+    self.map.set_layer_meta(meta)
+  }
+
+  /// Reserves capacity for at least `additional` more keys.
+  #[inline]
+  pub fn reserve(&mut self, additional: usize) {
+    self.map.reserve(additional)
+  }
+
+  /// Reserves capacity for at least `additional` more layers.
+  #[inline]
+  pub fn reserve_layers(&mut self, additional: usize) {
+    self.map.reserve_layers(additional)
+  }
+
+  /// Reserves capacity for at least `additional` more keys to be defined in the topmost layer.
+  #[inline]
+  pub fn reserve_in_top(&mut self, additional: usize) {
+    self.map.reserve_in_top(additional)
+  }
+
+  /// Removes keys with no remaining bindings from the key table, then shrinks the key table, value stacks and layers as much as possible.
+  ///
+  /// No bindings change, so the observer is not notified.
+  #[inline]
+  pub fn shrink_to_fit(&mut self) {
+    self.map.shrink_to_fit()
+  }
+
+  /// Removes all elements and additional layers.
+  ///
+  /// The observer sees each layer being popped, followed by the removal of every binding in the base layer.
+  #[inline]
+  pub fn clear_all(&mut self) {
+    observed_clear_all(&mut self.map, &mut self.observer)
+  }
+
+  /// Sets the policy used by `try_define()` to handle keys that are already defined in the top layer.
+  #[inline]
+  pub fn set_redefinition_policy(&mut self, policy: RedefinitionPolicy) {
+    self.map.set_redefinition_policy(policy)
+  }
+
+  /// Enables or disables read tracking.
+  #[inline]
+  pub fn set_read_tracking(&mut self, enabled: bool) {
+    self.map.set_read_tracking(enabled)
+  }
+}
+
+/// A [`ScopeSet`] that notifies a [`ScopeObserver`] of every change made through it.
+///
+/// Created by [`ScopeSet::observe`]. The wrapper dereferences to the underlying set for read access;
+/// changes must go through the wrapper's own methods so that the observer sees them.
+/// Every mutating method of the set is forwarded except `scope()`, `with_layer()` and `with_layer_meta()`,
+/// which would let changes bypass the observer and are not available. Layer metadata and capacity changes are forwarded but not observed.
+/// Sets without an observer pay nothing for this feature.
+pub struct ObservedScopeSet<T, O, S: BuildHasher, const STACK: usize = 1, const LAYERS: usize = 1> {
+  set: ScopeSet<T, S, STACK, LAYERS>,
+  observer: O,
+}
+
+impl<T, O, S: BuildHasher, const STACK: usize, const LAYERS: usize> ObservedScopeSet<T, O, S, STACK, LAYERS> {
+  #[inline]
+  pub(crate) fn new(set: ScopeSet<T, S, STACK, LAYERS>, observer: O) -> Self {
+    Self {
+      set,
+      observer,
+    }
+  }
+
+  /// Gets a reference to the observer.
+  #[inline]
+  pub fn observer(&self) -> &O {
+    &self.observer
+  }
+
+  /// Gets a mutable reference to the observer.
+  #[inline]
+  pub fn observer_mut(&mut self) -> &mut O {
+    &mut self.observer
+  }
+
+  /// Detaches the observer and returns it along with the set.
+  #[inline]
+  pub fn into_inner(self) -> (ScopeSet<T, S, STACK, LAYERS>, O) {
+    (self.set, self.observer)
+  }
+}
+
+impl<T, O, S: BuildHasher, const STACK: usize, const LAYERS: usize> Deref for ObservedScopeSet<T, O, S, STACK, LAYERS> {
+  type Target = ScopeSet<T, S, STACK, LAYERS>;
+
+  #[inline]
+  fn deref(&self) -> &Self::Target {
+    &self.set
+  }
+}
+
+impl<T: Eq + Hash, O: ScopeObserver<T, ()>, S: BuildHasher, const STACK: usize, const LAYERS: usize> ObservedScopeSet<T, O, S, STACK, LAYERS> {
+  /// Adds a new, empty layer.
+  #[inline]
+  pub fn push_layer(&mut self) {
+    observed_push_layer(&mut self.set.map, &mut self.observer)
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys.
+  /// Returns `true` if a layer was removed.
+  #[inline]
+  pub fn pop_layer(&mut self) -> bool {
+    observed_pop_layer(&mut self.set.map, &mut self.observer)
+  }
+
+  /// Adds the specified key to the topmost layer.
+  #[inline]
+  pub fn define(&mut self, key: T) {
+    let height = self.set.depth() - 1;
+    observed_define(&mut self.set.map, &mut self.observer, key, (), height);
+  }
+
+  /// Adds the specified key to the layer `min_depth` layers below the top layer. Saturates to base layer.
+  #[inline]
+  pub fn define_parent(&mut self, key: T, min_depth: usize) {
+    let height = (self.set.depth() - 1).saturating_sub(min_depth);
+    observed_define(&mut self.set.map, &mut self.observer, key, (), height);
+  }
+
+  /// Removes the specified key from the topmost layer.
+  /// Returns `true` if the key was removed.
+  #[inline]
+  pub fn remove<Q>(&mut self, key: &Q) -> bool
+  where
+    T: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    observed_remove(&mut self.set.map, &mut self.observer, key).is_some()
+  }
+
+  /// Removes all keys in the topmost layer.
+  #[inline]
+  pub fn clear_top(&mut self) {
+    observed_clear_top(&mut self.set.map, &mut self.observer)
+  }
+
+  /// Adds a new, empty layer and returns its ID.
+  #[inline]
+  pub fn push_layer_id(&mut self) -> LayerId {
+    observed_push_layer(&mut self.set.map, &mut self.observer);
+    self.set.top_layer_id()
+  }
+
+  /// Removes the topmost layer if its ID matches `id`.
+  ///
+  /// Returns an error if `id` does not identify the topmost layer, or if it identifies the base layer.
+  #[inline]
+  pub fn pop_layer_checked(&mut self, id: LayerId) -> Result<(), PopLayerError> {
+    observed_pop_layer_checked(&mut self.set.map, &mut self.observer, id)
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys.
+  /// Returns the keys defined in the removed layer that were never read, or `None` if no layer was removed.
+  #[inline]
+  pub fn pop_layer_reporting(&mut self) -> Option<Vec<&T>> {
+    observed_pop_layer_reporting(&mut self.set.map, &mut self.observer)
+  }
+
+  /// Adds a new layer containing the specified keys.
+  #[inline]
+  pub fn push_layer_from(&mut self, keys: impl IntoIterator<Item = T>) {
+    self.push_layer();
+    self.define_many(keys);
+  }
+
+  /// Adds all of the specified keys to the topmost layer.
+  #[inline]
+  pub fn define_many(&mut self, keys: impl IntoIterator<Item = T>) {
+    for key in keys {
+      self.define(key);
+    }
+  }
+
+  /// Adds the specified key to the topmost layer, applying the set's [`RedefinitionPolicy`].
+  ///
+  /// The observer is only notified if the key is defined.
+  #[inline]
+  pub fn try_define(&mut self, key: T) -> Result<DefineOutcome<()>, RedefinitionError<T, ()>> {
+    let height = self.set.depth() - 1;
+    let (stack_index, outcome) = self.set.map.try_define_index(key, ())?;
+    notify_define(&self.set.map, &mut self.observer, stack_index, height);
+    Ok(outcome)
+  }
+
+  /// Adds the specified key to the topmost layer.
+  /// If the key hides a definition in a lower layer, returns the height of the hidden definition.
+  #[inline]
+  pub fn define_reporting(&mut self, key: T) -> Option<usize> {
+    let height = self.set.depth() - 1;
+    let stack_index = observed_define(&mut self.set.map, &mut self.observer, key, (), height);
+    self.set.map.stack_at(stack_index).1.iter().rev().nth(1).map(|var| var.layer)
+  }
+
+  /// Adds the specified key to the layer identified by `id`.
+  ///
+  /// Returns `false` and discards the key if the layer no longer exists.
+  #[inline]
+  pub fn define_in(&mut self, id: LayerId, key: T) -> bool {
+    if !self.set.has_layer(id) {
+      return false
+    }
+    observed_define(&mut self.set.map, &mut self.observer, key, (), id.height());
+    true
+  }
+
+  /// Adds a new, empty layer with the specified metadata attached.
+  #[inline]
+  pub fn push_layer_with_meta<M: Any + Send + Sync>(&mut self, meta: M) {
+    observed_push_layer(&mut self.set.map, &mut self.observer);
+    self.set.set_layer_meta(meta);
+  }
+
+  /// Attaches metadata to the topmost layer, replacing any metadata it already has.
+  ///
+  /// Layer metadata is not observed.
+  #[inline]
+  pub fn set_layer_meta<M: Any + Send + Sync>(&mut self, meta: M) {
+    self.set.set_layer_meta(meta)
+  }
+
+  /// Reserves capacity for at least `additional` more keys.
+  #[inline]
+  pub fn reserve(&mut self, additional: usize) {
+    self.set.reserve(additional)
+  }
+
+  /// Reserves capacity for at least `additional` more layers.
+  #[inline]
+  pub fn reserve_layers(&mut self, additional: usize) {
+    self.set.reserve_layers(additional)
+  }
+
+  /// Reserves capacity for at least `additional` more keys to be defined in the topmost layer.
+  #[inline]
+  pub fn reserve_in_top(&mut self, additional: usize) {
+    self.set.reserve_in_top(additional)
+  }
+
+  /// Removes keys with no remaining definitions from the key table, then shrinks the set's storage as much as possible.
+  ///
+  /// No keys are defined or removed, so the observer is not notified.
+  #[inline]
+  pub fn shrink_to_fit(&mut self) {
+    self.set.shrink_to_fit()
+  }
+
+  /// Removes all keys and additional layers.
+  ///
+  /// The observer sees each layer being popped, followed by the removal of every key in the base layer.
+  #[inline]
+  pub fn clear_all(&mut self) {
+    observed_clear_all(&mut self.set.map, &mut self.observer)
+  }
+
+  /// Sets the policy used by `try_define()` to handle keys that are already defined in the top layer.
+  #[inline]
+  pub fn set_redefinition_policy(&mut self, policy: RedefinitionPolicy) {
+    self.set.set_redefinition_policy(policy)
+  }
+
+  /// Enables or disables read tracking.
+  #[inline]
+  pub fn set_read_tracking(&mut self, enabled: bool) {
+    self.set.set_read_tracking(enabled)
+  }
+}
+
+#[inline]
+fn observed_push_layer<K, V, O: ScopeObserver<K, V>, S: BuildHasher, const STACK: usize, const LAYERS: usize>(map: &mut ScopeMap<K, V, S, STACK, LAYERS>, observer: &mut O) {
+  map.push_layer();
+  observer.on_push_layer(map.depth() - 1);
+}
+
+#[inline]
+fn observed_pop_layer<K, V, O: ScopeObserver<K, V>, S: BuildHasher, const STACK: usize, const LAYERS: usize>(map: &mut ScopeMap<K, V, S, STACK, LAYERS>, observer: &mut O) -> bool {
+  let height = map.depth() - 1;
+  let mut removed_indices = vec![];
+  if !map.pop_layer_with(|stack_index, _| removed_indices.push(stack_index)) {
+    return false
+  }
+  let removed_keys: Vec<&K> = removed_indices.into_iter().map(|i| map.stack_at(i).0).collect();
+  observer.on_pop_layer(height, &removed_keys);
+  true
+}
+
+#[inline]
+fn observed_pop_layer_checked<K, V, O: ScopeObserver<K, V>, S: BuildHasher, const STACK: usize, const LAYERS: usize>(
+  map: &mut ScopeMap<K, V, S, STACK, LAYERS>,
+  observer: &mut O,
+  id: LayerId
+) -> Result<(), PopLayerError> {
+  let top = map.top_layer_id();
+  if top != id {
+    return Err(PopLayerError::Mismatch { expected: id, top })
+  }
+  if observed_pop_layer(map, observer) {
+    Ok(())
+  } else {
+    Err(PopLayerError::BaseLayer)
+  }
+}
+
+#[inline]
+fn observed_pop_layer_reporting<'m, K, V, O: ScopeObserver<K, V>, S: BuildHasher, const STACK: usize, const LAYERS: usize>(
+  map: &'m mut ScopeMap<K, V, S, STACK, LAYERS>,
+  observer: &mut O
+) -> Option<Vec<&'m K>> {
+  let unread = map.unread_in_top();
+  if !observed_pop_layer(map, observer) {
+    return None
+  }
+  let map = &*map;
+  Some(unread.into_iter().map(|i| map.stack_at(i).0).collect())
+}
+
+/// Defines an entry in the layer at `height` and notifies the observer. Returns the key's map index.
+#[inline]
+fn observed_define<K: Eq + Hash, V, O: ScopeObserver<K, V>, S: BuildHasher, const STACK: usize, const LAYERS: usize>(
+  map: &mut ScopeMap<K, V, S, STACK, LAYERS>,
+  observer: &mut O,
+  key: K,
+  value: V,
+  height: usize
+) -> usize {
+  let stack_index = map.define_at_height(key, value, height);
+  notify_define(map, observer, stack_index, height);
+  stack_index
+}
+
+/// Notifies the observer of the binding that was just defined at `height` in the stack at `stack_index`.
+#[inline]
+fn notify_define<K, V, O: ScopeObserver<K, V>, S: BuildHasher, const STACK: usize, const LAYERS: usize>(
+  map: &ScopeMap<K, V, S, STACK, LAYERS>,
+  observer: &mut O,
+  stack_index: usize,
+  height: usize
+) {
+  let (key, stack) = map.stack_at(stack_index);
+  let position = find_in_stack(stack, height).unwrap();
+  observer.on_define(key, &stack[position].value, height);
+  if let Some(shadowed) = position.checked_sub(1).map(|i| &stack[i]) {
+    observer.on_shadow(key, height, shadowed.layer);
+  }
+}
+
+#[inline]
+fn notify_get_mut<K, V, O: ScopeObserver<K, V>, S: BuildHasher, Q, const STACK: usize, const LAYERS: usize>(
+  map: &ScopeMap<K, V, S, STACK, LAYERS>,
+  observer: &mut O,
+  key: &Q,
+  height: usize
+)
+where
+  K: Eq + Hash + Borrow<Q>,
+  Q: ?Sized + Eq + Hash,
+{
+  let stack_index = map.index_of_equivalent(key).unwrap();
+  observer.on_get_mut(map.stack_at(stack_index).0, height);
+}
+
+#[inline]
+fn observed_remove<K, V, O: ScopeObserver<K, V>, S: BuildHasher, Q, const STACK: usize, const LAYERS: usize>(
+  map: &mut ScopeMap<K, V, S, STACK, LAYERS>,
+  observer: &mut O,
+  key: &Q
+) -> Option<V>
+where
+  K: Eq + Hash + Borrow<Q>,
+  Q: ?Sized + Eq + Hash,
+{
+  let height = map.depth() - 1;
+  let stack_index = map.index_of_equivalent(key)?;
+  let value = map.remove(key)?;
+  observer.on_remove(map.stack_at(stack_index).0, &value, height);
+  Some(value)
+}
+
+#[inline]
+fn observed_clear_top<K: Eq + Hash, V, O: ScopeObserver<K, V>, S: BuildHasher, const STACK: usize, const LAYERS: usize>(map: &mut ScopeMap<K, V, S, STACK, LAYERS>, observer: &mut O) {
+  let height = map.depth() - 1;
+  let mut removed = vec![];
+  map.clear_top_with(|stack_index, var| removed.push((stack_index, var.value)));
+  for (stack_index, value) in removed {
+    observer.on_remove(map.stack_at(stack_index).0, &value, height);
+  }
+}
+
+#[inline]
+fn observed_clear_all<K: Eq + Hash, V, O: ScopeObserver<K, V>, S: BuildHasher, const STACK: usize, const LAYERS: usize>(map: &mut ScopeMap<K, V, S, STACK, LAYERS>, observer: &mut O) {
+  while observed_pop_layer(map, observer) {}
+  observed_clear_top(map, observer);
+  map.clear_all();
+}
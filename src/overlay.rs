@@ -0,0 +1,300 @@
+use std::{borrow::Borrow, hash::{BuildHasher, Hash}, sync::Arc};
+
+use crate::ScopeMap;
+
+/// A map of local layers stacked on top of the layers of a shared map.
+///
+/// This is the common core of [`ForkedScopeMap`](crate::ForkedScopeMap) and [`ChildScopeMap`](crate::ChildScopeMap).
+///
+/// The local map's base layer is never used; the overlay's own layers start at height 1 of the local map,
+/// and sit directly above the shared map's topmost layer.
+/// Changes that reach into the shared layers are either made to a copy of the binding in the lowest own layer
+/// (`*_own` methods, for overlays that always keep an own layer), or are copy-on-write: the shared map is cloned
+/// the first time it is modified while other maps still hold it.
+#[derive(Clone)]
+pub(crate) struct Overlay<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> {
+  /// The layers shared with other maps.
+  base: Arc<ScopeMap<K, V, S, STACK, LAYERS>>,
+  /// The overlay's own layers, above an unused base layer.
+  local: ScopeMap<K, V, S, STACK, LAYERS>,
+  /// The number of own layers that can't be popped.
+  min_own_depth: usize,
+}
+
+impl<K, V, S: Clone + BuildHasher, const STACK: usize, const LAYERS: usize> Overlay<K, V, S, STACK, LAYERS> {
+  /// Creates an overlay over `base` with `min_own_depth` empty layers of its own, which can't be popped.
+  ///
+  /// The local map uses the base map's hasher, redefinition policy and read tracking setting.
+  #[inline]
+  pub(crate) fn new(base: Arc<ScopeMap<K, V, S, STACK, LAYERS>>, min_own_depth: usize) -> Self {
+    let mut local = ScopeMap::configured_with_hasher(base.hasher().clone());
+    local.set_redefinition_policy(base.redefinition_policy());
+    local.set_read_tracking(base.is_tracking_reads());
+    for _ in 0..min_own_depth {
+      local.push_layer();
+    }
+    Self {
+      base,
+      local,
+      min_own_depth,
+    }
+  }
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> Overlay<K, V, S, STACK, LAYERS> {
+  #[inline]
+  pub(crate) fn base(&self) -> &Arc<ScopeMap<K, V, S, STACK, LAYERS>> {
+    &self.base
+  }
+
+  #[inline]
+  pub(crate) fn depth(&self) -> usize {
+    self.base.depth() + self.own_depth()
+  }
+
+  #[inline]
+  pub(crate) fn own_depth(&self) -> usize {
+    self.local.depth() - 1
+  }
+
+  #[inline]
+  pub(crate) fn push_layer(&mut self) {
+    self.local.push_layer()
+  }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> Overlay<K, V, S, STACK, LAYERS> {
+  /// Removes all of the overlay's own entries and layers, except for its empty unpoppable layers.
+  #[inline]
+  pub(crate) fn clear_own(&mut self) {
+    self.local.clear_all();
+    for _ in 0..self.min_own_depth {
+      self.local.push_layer();
+    }
+  }
+
+  /// Removes the topmost own layer, unless it is one of the unpoppable layers. Returns `true` if a layer was removed.
+  #[inline]
+  pub(crate) fn pop_own_layer(&mut self) -> bool {
+    self.own_depth() > self.min_own_depth && self.local.pop_layer()
+  }
+
+  /// Adds an entry to the topmost own layer, which must exist.
+  #[inline]
+  pub(crate) fn define_own(&mut self, key: K, value: V) {
+    debug_assert!(self.own_depth() > 0);
+    self.local.define(key, value)
+  }
+
+  /// Adds an entry in the own layer `min_depth` layers from the top, which must exist. Saturates to the lowest own layer.
+  #[inline]
+  pub(crate) fn define_parent_own(&mut self, key: K, value: V, min_depth: usize) {
+    let own_depth = self.own_depth();
+    debug_assert!(own_depth > 0);
+    self.local.define_parent(key, value, min_depth.min(own_depth - 1))
+  }
+
+  /// Removes an entry from the topmost own layer, which must exist, and returns its value.
+  #[inline]
+  pub(crate) fn remove_own<Q>(&mut self, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    debug_assert!(self.own_depth() > 0);
+    self.local.remove(key)
+  }
+
+  /// Removes all entries in the topmost own layer, which must exist.
+  #[inline]
+  pub(crate) fn clear_own_top(&mut self) {
+    debug_assert!(self.own_depth() > 0);
+    self.local.clear_top()
+  }
+
+  #[inline]
+  pub(crate) fn len(&self) -> usize {
+    self.base.len() + self.local.keys().filter(|key| self.base.height_of(*key).is_none()).count()
+  }
+
+  #[inline]
+  pub(crate) fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.local.contains_key(key) || self.base.contains_key(key)
+  }
+
+  #[inline]
+  pub(crate) fn contains_key_at_top<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    if self.own_depth() > 0 {
+      self.local.contains_key_at_top(key)
+    } else {
+      self.base.contains_key_at_top(key)
+    }
+  }
+
+  #[inline]
+  pub(crate) fn get<Q>(&self, key: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.local.get(key).or_else(|| self.base.get(key))
+  }
+
+  #[inline]
+  pub(crate) fn get_parent<Q>(&self, key: &Q, min_depth: usize) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let own_depth = self.own_depth();
+    if min_depth < own_depth {
+      self.local.get_parent(key, min_depth).or_else(|| self.base.get(key))
+    } else {
+      self.base.get_parent(key, min_depth - own_depth)
+    }
+  }
+
+  #[inline]
+  pub(crate) fn height_of<Q>(&self, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    match self.local.height_of(key) {
+      Some(height) => Some(self.base.depth() - 1 + height),
+      None => self.base.height_of(key),
+    }
+  }
+
+  #[inline]
+  pub(crate) fn depth_of<Q>(&self, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.height_of(key).map(|height| self.depth() - 1 - height)
+  }
+
+  #[inline]
+  pub(crate) fn iter(&self) -> impl Iterator<Item = (&'_ K, &'_ V)> {
+    self.local
+      .iter()
+      .chain(self.base.iter().filter(move |(key, _)| self.local.height_of(*key).is_none()))
+  }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone, S: BuildHasher, const STACK: usize, const LAYERS: usize> Overlay<K, V, S, STACK, LAYERS> {
+  /// Gets a mutable reference to the topmost value associated with a key.
+  ///
+  /// A binding found in the shared layers is first copied into the lowest own layer, which must exist,
+  /// so only that binding is cloned.
+  #[inline]
+  pub(crate) fn get_mut_own<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    debug_assert!(self.own_depth() > 0);
+    if self.local.height_of(key).is_none() {
+      let (shared_key, value) = self.base.get_key_value(key)?;
+      self.local.define_at_height(shared_key.clone(), value.clone(), 1);
+    }
+    self.local.get_mut(key)
+  }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone, S: Clone + BuildHasher, const STACK: usize, const LAYERS: usize> Overlay<K, V, S, STACK, LAYERS> {
+  /// Gets mutable access to the shared layers, cloning them first if they are shared with other maps.
+  #[inline]
+  fn base_mut(&mut self) -> &mut ScopeMap<K, V, S, STACK, LAYERS> {
+    Arc::make_mut(&mut self.base)
+  }
+
+  #[inline]
+  pub(crate) fn pop_layer(&mut self) -> bool {
+    if self.own_depth() > 0 {
+      self.pop_own_layer()
+    } else if self.base.depth() > 1 {
+      self.base_mut().pop_layer()
+    } else {
+      false
+    }
+  }
+
+  #[inline]
+  pub(crate) fn define(&mut self, key: K, value: V) {
+    if self.own_depth() > 0 {
+      self.define_own(key, value)
+    } else {
+      self.base_mut().define(key, value)
+    }
+  }
+
+  #[inline]
+  pub(crate) fn define_parent(&mut self, key: K, value: V, min_depth: usize) {
+    let own_depth = self.own_depth();
+    if min_depth < own_depth {
+      self.local.define_parent(key, value, min_depth)
+    } else {
+      self.base_mut().define_parent(key, value, min_depth - own_depth)
+    }
+  }
+
+  #[inline]
+  pub(crate) fn remove<Q>(&mut self, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    if self.own_depth() > 0 {
+      self.remove_own(key)
+    } else if self.base.contains_key_at_top(key) {
+      self.base_mut().remove(key)
+    } else {
+      None
+    }
+  }
+
+  #[inline]
+  pub(crate) fn clear_top(&mut self) {
+    if self.own_depth() > 0 {
+      self.clear_own_top()
+    } else if !self.base.is_top_empty() {
+      self.base_mut().clear_top()
+    }
+  }
+
+  #[inline]
+  pub(crate) fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    if self.local.height_of(key).is_some() {
+      self.local.get_mut(key)
+    } else if self.base.height_of(key).is_some() {
+      self.base_mut().get_mut(key)
+    } else {
+      None
+    }
+  }
+
+  pub(crate) fn into_map(self) -> ScopeMap<K, V, S, STACK, LAYERS> {
+    let mut map = Arc::try_unwrap(self.base).unwrap_or_else(|base| (*base).clone());
+    for height in 1..self.local.depth() {
+      map.push_layer();
+      for (_, key, value) in self.local.layer_entries(height) {
+        map.define(key.clone(), value.clone());
+      }
+    }
+    map
+  }
+}
@@ -0,0 +1,292 @@
+//! A straightforward reference implementation of [`ScopeMap`](crate::ScopeMap), for differential testing.
+//!
+//! [`NaiveScopeMap`] stores each layer as a separate `HashMap` and answers every query by scanning the layers.
+//! It is far slower than `ScopeMap`, but simple enough to be obviously correct,
+//! which makes it useful for fuzzing code that wraps or extends `ScopeMap`.
+
+use std::{borrow::Borrow, collections::{HashMap, HashSet}, hash::Hash};
+
+/// A layered hash map implemented as a stack of `HashMap`s.
+///
+/// Its methods have the same names and semantics as their [`ScopeMap`](crate::ScopeMap) counterparts.
+#[derive(Debug, Clone)]
+pub struct NaiveScopeMap<K, V> {
+  layers: Vec<HashMap<K, V>>,
+}
+
+impl<K, V> Default for NaiveScopeMap<K, V> {
+  #[inline]
+  fn default() -> Self {
+    Self {
+      layers: vec![HashMap::new()],
+    }
+  }
+}
+
+impl<K, V> NaiveScopeMap<K, V> {
+  /// Creates an empty `NaiveScopeMap`.
+  #[inline]
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Gets the number of layers in the map.
+  #[inline]
+  pub fn depth(&self) -> usize {
+    self.layers.len()
+  }
+
+  /// Adds a new, empty layer.
+  #[inline]
+  pub fn push_layer(&mut self) {
+    self.layers.push(HashMap::new())
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys/values.
+  /// Returns `true` if a layer was removed.
+  #[inline]
+  pub fn pop_layer(&mut self) -> bool {
+    if self.layers.len() > 1 {
+      self.layers.pop();
+      return true
+    }
+    false
+  }
+
+  /// Removes all entries in the topmost layer.
+  #[inline]
+  pub fn clear_top(&mut self) {
+    self.layers.last_mut().unwrap().clear()
+  }
+
+  /// Removes all elements and additional layers.
+  #[inline]
+  pub fn clear_all(&mut self) {
+    self.layers.clear();
+    self.layers.push(HashMap::new());
+  }
+}
+
+impl<K: Eq + Hash, V> NaiveScopeMap<K, V> {
+  /// Gets the number of unique keys in the map.
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.keys().len()
+  }
+
+  /// Returns `true` if no keys are defined.
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.layers.iter().all(|layer| layer.is_empty())
+  }
+
+  /// Adds the specified entry to the topmost layer.
+  #[inline]
+  pub fn define(&mut self, key: K, value: V) {
+    self.layers.last_mut().unwrap().insert(key, value);
+  }
+
+  /// Adds the specified entry in the layer `min_depth` layers from the top. Saturates to base layer.
+  #[inline]
+  pub fn define_parent(&mut self, key: K, value: V, min_depth: usize) {
+    let height = (self.depth() - 1).saturating_sub(min_depth);
+    self.layers[height].insert(key, value);
+  }
+
+  /// Removes the entry with the specified key from the topmost layer and returns its value.
+  #[inline]
+  pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.layers.last_mut().unwrap().remove(key)
+  }
+
+  /// Returns `true` if the map contains the specified key in any layer.
+  #[inline]
+  pub fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.height_of(key).is_some()
+  }
+
+  /// Returns `true` if the map contains the specified key at the top layer.
+  #[inline]
+  pub fn contains_key_at_top<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.layers.last().unwrap().contains_key(key)
+  }
+
+  /// Gets a reference to the topmost value associated with a key.
+  #[inline]
+  pub fn get<Q>(&self, key: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.get_parent(key, 0)
+  }
+
+  /// Gets a mutable reference to the topmost value associated with a key.
+  #[inline]
+  pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.layers.iter_mut().rev().find_map(|layer| layer.get_mut(key))
+  }
+
+  /// Gets references to all the values associated with a key, starting with the topmost and going down.
+  #[inline]
+  pub fn get_all<Q>(&self, key: &Q) -> Vec<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.layers.iter().rev().filter_map(|layer| layer.get(key)).collect()
+  }
+
+  /// Gets a reference to the value associated with a key at least `min_depth` layers below the topmost layer.
+  #[inline]
+  pub fn get_parent<Q>(&self, key: &Q, min_depth: usize) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.get_parent_height(key, min_depth).map(|(value, _)| value)
+  }
+
+  /// Gets a reference to the value associated with a key at least `min_depth` layers below the topmost layer,
+  /// as well as its associated depth.
+  #[inline]
+  pub fn get_parent_depth<Q>(&self, key: &Q, min_depth: usize) -> Option<(&V, usize)>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.get_parent_height(key, min_depth).map(|(value, height)| (value, self.depth() - 1 - height))
+  }
+
+  /// Gets a reference to the value associated with a key at least `min_depth` layers below the topmost layer,
+  /// as well as its associated height.
+  #[inline]
+  pub fn get_parent_height<Q>(&self, key: &Q, min_depth: usize) -> Option<(&V, usize)>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let max_height = (self.depth() - 1).checked_sub(min_depth)?;
+    (0..=max_height).rev().find_map(|height| self.layers[height].get(key).map(|value| (value, height)))
+  }
+
+  /// Gets the depth of the specified key (i.e. how many layers down from the top that the key first appears).
+  #[inline]
+  pub fn depth_of<Q>(&self, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.height_of(key).map(|height| self.depth() - 1 - height)
+  }
+
+  /// Gets the height of the specified key (i.e. how many layers up from the bottom that the key last appears).
+  #[inline]
+  pub fn height_of<Q>(&self, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.get_parent_height(key, 0).map(|(_, height)| height)
+  }
+
+  /// Gets the set of all keys defined in any layer.
+  #[inline]
+  pub fn keys(&self) -> HashSet<&K> {
+    self.layers.iter().flat_map(|layer| layer.keys()).collect()
+  }
+
+  /// Gets the set of keys defined in the topmost layer.
+  #[inline]
+  pub fn keys_top(&self) -> HashSet<&K> {
+    self.layers.last().unwrap().keys().collect()
+  }
+
+  /// Gets every visible key along with its topmost value.
+  #[inline]
+  pub fn iter(&self) -> HashMap<&K, &V> {
+    let mut visible = HashMap::new();
+    for layer in self.layers.iter() {
+      visible.extend(layer.iter());
+    }
+    visible
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::ScopeMap;
+
+  /// A small linear congruential generator, so the test is deterministic without extra dependencies.
+  struct Lcg(u64);
+
+  impl Lcg {
+    fn next(&mut self, bound: u64) -> u64 {
+      self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+      (self.0 >> 33) % bound
+    }
+  }
+
+  #[test]
+  fn reference_matches_scope_map() {
+    let mut rng = Lcg(0x5eed);
+    let mut map: ScopeMap<u64, u64> = ScopeMap::new();
+    let mut naive: NaiveScopeMap<u64, u64> = NaiveScopeMap::new();
+
+    for step in 0..20_000 {
+      let key = rng.next(8);
+      match rng.next(8) {
+        0 | 1 => {
+          map.define(key, step);
+          naive.define(key, step);
+        },
+        2 => {
+          let min_depth = rng.next(4) as usize;
+          map.define_parent(key, step, min_depth);
+          naive.define_parent(key, step, min_depth);
+        },
+        3 => assert_eq!(naive.remove(&key), map.remove(&key)),
+        4 => {
+          map.push_layer();
+          naive.push_layer();
+        },
+        5 => assert_eq!(naive.pop_layer(), map.pop_layer()),
+        6 if rng.next(10) == 0 => {
+          map.clear_top();
+          naive.clear_top();
+        },
+        _ => {},
+      }
+
+      map.check_invariants().unwrap();
+      let min_depth = rng.next(4) as usize;
+      assert_eq!(naive.depth(), map.depth());
+      assert_eq!(naive.len(), map.len());
+      assert_eq!(naive.get(&key), map.get(&key));
+      assert_eq!(naive.contains_key_at_top(&key), map.contains_key_at_top(&key));
+      assert_eq!(naive.depth_of(&key), map.depth_of(&key));
+      assert_eq!(naive.get_parent_depth(&key, min_depth), map.get_parent_depth(&key, min_depth));
+      assert_eq!(naive.get_parent_height(&key, min_depth), map.get_parent_height(&key, min_depth));
+      assert_eq!(naive.get_all(&key), map.get_all(&key).map_or(vec![], |values| values.collect()));
+      assert_eq!(naive.keys_top(), map.keys_top().collect());
+      assert_eq!(naive.iter(), map.iter().collect());
+    }
+  }
+}
@@ -0,0 +1,101 @@
+use std::{fmt::{self, Debug, Display, Write}, hash::BuildHasher};
+
+use crate::ScopeMap;
+
+/// Displays the layers of a [`ScopeMap`] from the top down, created by [`ScopeMap::display_layers`].
+///
+/// Each layer is listed with its height, followed by the keys defined in it and their values.
+pub struct LayersDisplay<'a, K, V, S: BuildHasher, const STACK: usize = 1, const LAYERS: usize = 1> {
+  map: &'a ScopeMap<K, V, S, STACK, LAYERS>,
+}
+
+impl<'a, K: Debug, V: Debug, S: BuildHasher, const STACK: usize, const LAYERS: usize> Display for LayersDisplay<'a, K, V, S, STACK, LAYERS> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for height in (0..self.map.depth()).rev() {
+      writeln!(f, "layer {}", height)?;
+      for (_, key, value) in self.map.layer_entries(height) {
+        writeln!(f, "  {:?} = {:?}", key, value)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Returns a value that displays each layer from the top down, along with its keys and values.
+  ///
+  /// Keys within a layer are listed in the order they were first added to the map.
+  #[inline]
+  pub fn display_layers(&self) -> LayersDisplay<'_, K, V, S, STACK, LAYERS> {
+    LayersDisplay {
+      map: self
+    }
+  }
+}
+
+impl<K: Debug, V: Debug, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeMap<K, V, S, STACK, LAYERS> {
+  /// Renders the map as a graph in the Graphviz DOT language.
+  ///
+  /// Each layer is drawn as a cluster containing one node per binding defined in it.
+  /// Each binding that hides a binding of the same key in a lower layer has an edge pointing to the hidden binding.
+  pub fn to_dot(&self) -> String {
+    let mut dot = String::from("digraph scopes {\n  node [shape=box];\n");
+    let mut edges = String::new();
+
+    for height in (0..self.depth()).rev() {
+      writeln!(dot, "  subgraph cluster_{} {{", height).unwrap();
+      writeln!(dot, "    label=\"layer {}\";", height).unwrap();
+      for (index, key, value) in self.layer_entries(height) {
+        let label = escape_dot(&format!("{:?} = {:?}", key, value));
+        writeln!(dot, "    n{}_{} [label=\"{}\"];", index, height, label).unwrap();
+        let stack = self.stack_at(index).1;
+        let position = stack.partition_point(|var| var.layer < height);
+        if let Some(shadowed) = position.checked_sub(1).map(|i| &stack[i]) {
+          writeln!(edges, "  n{}_{} -> n{}_{} [label=\"shadows\"];", index, height, index, shadowed.layer).unwrap();
+        }
+      }
+      dot.push_str("  }\n");
+    }
+
+    dot.push_str(&edges);
+    dot.push_str("}\n");
+    dot
+  }
+}
+
+/// Escapes a string for use inside a quoted DOT identifier.
+fn escape_dot(s: &str) -> String {
+  s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+  use crate::ScopeMap;
+
+  fn sample() -> ScopeMap<&'static str, i32> {
+    let mut map = ScopeMap::new();
+    map.define("foo", 1);
+    map.define("bar", 2);
+    map.push_layer();
+    map.push_layer();
+    map.define("foo", 3);
+    map
+  }
+
+  #[test]
+  fn render_display_layers() {
+    let expected = "layer 2\n  \"foo\" = 3\nlayer 1\nlayer 0\n  \"foo\" = 1\n  \"bar\" = 2\n";
+    assert_eq!(expected, sample().display_layers().to_string());
+  }
+
+  #[test]
+  fn render_to_dot() {
+    let dot = sample().to_dot();
+    assert!(dot.starts_with("digraph scopes {"));
+    assert!(dot.contains("subgraph cluster_1 {"));
+    assert!(dot.contains("n0_2 [label=\"\\\"foo\\\" = 3\"];"));
+    assert!(dot.contains("n1_0 [label=\"\\\"bar\\\" = 2\"];"));
+    assert!(dot.contains("n0_2 -> n0_0 [label=\"shadows\"];"));
+    assert_eq!(1, dot.matches("->").count());
+  }
+}
@@ -1,14 +1,16 @@
-use std::{collections::{hash_map::RandomState}, hash::BuildHasher, hash::Hash, borrow::Borrow};
+use std::{any::Any, collections::{hash_map::RandomState}, hash::BuildHasher, hash::Hash, borrow::Borrow};
 
-use crate::ScopeMap;
+use crate::{DefineOutcome, LayerId, MemoryStats, ObservedScopeSet, PopLayerError, RedefinitionError, RedefinitionPolicy, ScopeMap, ScopeSetGuard};
 
 /// A layered hash set for representing the scopes of variables.
+///
+/// `STACK` and `LAYERS` configure inline capacities in the same way as for [`ScopeMap`].
 #[derive(Clone)]
-pub struct ScopeSet<T, S: BuildHasher = RandomState> {
-  map: ScopeMap<T, (), S>
+pub struct ScopeSet<T, S: BuildHasher = RandomState, const STACK: usize = 1, const LAYERS: usize = 1> {
+  pub(crate) map: ScopeMap<T, (), S, STACK, LAYERS>
 }
 
-impl<T, S: Default + BuildHasher> Default for ScopeSet<T, S> {
+impl<T, S: Default + BuildHasher, const STACK: usize, const LAYERS: usize> Default for ScopeSet<T, S, STACK, LAYERS> {
   /// Creates a new `ScopeSet` with the default configuration.
   #[inline]
   fn default() -> Self {
@@ -52,7 +54,42 @@ impl<T, S: BuildHasher> ScopeSet<T, S> {
   }
 }
 
-impl<T, S: BuildHasher> ScopeSet<T, S> {
+impl<T, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeSet<T, S, STACK, LAYERS> {
+  /// Creates an empty `ScopeSet` with the specified hasher and a default capacity, using the inline capacities given by the type.
+  ///
+  /// Unlike [`ScopeSet::with_hasher`], this works for any `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn configured_with_hasher(hash_builder: S) -> Self {
+    Self {
+      map: ScopeMap::configured_with_hasher(hash_builder)
+    }
+  }
+
+  /// Creates an empty `ScopeSet` with the specified capacity and hasher, using the inline capacities given by the type.
+  ///
+  /// Unlike [`ScopeSet::with_capacity_and_hasher`], this works for any `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn configured_with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+    Self {
+      map: ScopeMap::configured_with_capacity_and_hasher(capacity, hash_builder)
+    }
+  }
+
+  /// Creates an empty `ScopeSet` with a default hasher and the specified capacity, using the inline capacities given by the type.
+  ///
+  /// Unlike [`ScopeSet::with_capacity`], this works for any hasher, `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn configured_with_capacity(capacity: usize) -> Self
+  where
+    S: Default,
+  {
+    Self {
+      map: ScopeMap::configured_with_capacity(capacity)
+    }
+  }
+}
+
+impl<T, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeSet<T, S, STACK, LAYERS> {
 
   /// Returns `true` is the set is empty.
   #[inline]
@@ -66,6 +103,14 @@ impl<T, S: BuildHasher> ScopeSet<T, S> {
     self.map.capacity()
   }
 
+  /// Reports how much memory the set is using.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys and layers.
+  #[inline]
+  pub fn memory_stats(&self) -> MemoryStats {
+    self.map.memory_stats()
+  }
+
   /// Gets the number of unique keys in the set.
   #[inline]
   pub fn len(&self) -> usize {
@@ -78,6 +123,18 @@ impl<T, S: BuildHasher> ScopeSet<T, S> {
     self.map.depth()
   }
 
+  /// Gets the policy used by `try_define()` to handle keys that are already defined in the top layer.
+  #[inline]
+  pub fn redefinition_policy(&self) -> RedefinitionPolicy {
+    self.map.redefinition_policy()
+  }
+
+  /// Sets the policy used by `try_define()` to handle keys that are already defined in the top layer.
+  #[inline]
+  pub fn set_redefinition_policy(&mut self, policy: RedefinitionPolicy) {
+    self.map.set_redefinition_policy(policy)
+  }
+
   /// Adds a new, empty layer.
   ///
   /// Computes in **O(1)** time.
@@ -86,6 +143,109 @@ impl<T, S: BuildHasher> ScopeSet<T, S> {
     self.map.push_layer()
   }
 
+  /// Adds a new, empty layer and returns its ID.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn push_layer_id(&mut self) -> LayerId {
+    self.map.push_layer_id()
+  }
+
+  /// Gets the ID of the topmost layer.
+  #[inline]
+  pub fn top_layer_id(&self) -> LayerId {
+    self.map.top_layer_id()
+  }
+
+  /// Returns `true` if the layer identified by `id` still exists.
+  #[inline]
+  pub fn has_layer(&self, id: LayerId) -> bool {
+    self.map.has_layer(id)
+  }
+
+  /// Removes the topmost layer if its ID matches `id`.
+  ///
+  /// Returns an error if `id` does not identify the topmost layer, or if it identifies the base layer.
+  /// In both cases, the set is left unchanged.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys stored in the removed layer.
+  #[inline]
+  pub fn pop_layer_checked(&mut self, id: LayerId) -> Result<(), PopLayerError> {
+    self.map.pop_layer_checked(id)
+  }
+
+  /// Attaches an observer that is notified of every change made through the returned wrapper.
+  #[inline]
+  pub fn observe<O>(self, observer: O) -> ObservedScopeSet<T, O, S, STACK, LAYERS> {
+    ObservedScopeSet::new(self, observer)
+  }
+
+  /// Adds a new, empty layer and returns a guard that removes it when dropped.
+  ///
+  /// The guard dereferences to the set, and removes the layer on every exit path, including early returns and panics.
+  #[inline]
+  pub fn scope(&mut self) -> ScopeSetGuard<'_, T, S, STACK, LAYERS> {
+    ScopeSetGuard::new(self)
+  }
+
+  /// Adds a new, empty layer, calls `f` with the set, and removes the layer afterwards.
+  /// Returns the result of `f`.
+  ///
+  /// The layer is removed even if `f` panics.
+  #[inline]
+  pub fn with_layer<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+    let mut guard = self.scope();
+    f(&mut guard)
+  }
+
+  /// Adds a new, empty layer with the specified metadata attached, calls `f` with the set, and removes the layer afterwards.
+  /// Returns the result of `f`.
+  ///
+  /// The layer and its metadata are removed even if `f` panics.
+  #[inline]
+  pub fn with_layer_meta<M: Any + Send + Sync, R>(&mut self, meta: M, f: impl FnOnce(&mut Self) -> R) -> R {
+    let mut guard = self.scope();
+    guard.set_layer_meta(meta);
+    f(&mut guard)
+  }
+
+  /// Adds a new, empty layer with the specified metadata attached.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn push_layer_with_meta<M: Any + Send + Sync>(&mut self, meta: M) {
+    self.map.push_layer_with_meta(meta)
+  }
+
+  /// Attaches metadata to the topmost layer, replacing any metadata it already has.
+  ///
+  /// The metadata is dropped when the layer is popped.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn set_layer_meta<M: Any + Send + Sync>(&mut self, meta: M) {
+    self.map.set_layer_meta(meta)
+  }
+
+  /// Gets the metadata of the layer `depth` layers below the top.
+  /// A depth of 0 refers to the top layer.
+  ///
+  /// Returns `None` if there is no such layer, or if its metadata is missing or not of type `M`.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers with metadata.
+  #[inline]
+  pub fn layer_meta<M: Any>(&self, depth: usize) -> Option<&M> {
+    self.map.layer_meta(depth)
+  }
+
+  /// Finds the topmost layer whose metadata is of type `M`, returning the metadata and the layer's depth.
+  ///
+  /// Computes in **O(n)** time in relation to the number of layers with metadata.
+  #[inline]
+  pub fn find_layer_meta<M: Any>(&self) -> Option<(&M, usize)> {
+    self.map.find_layer_meta()
+  }
+
   /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys.
   /// Returns `true` if the layer was removed.
   ///
@@ -94,9 +254,63 @@ impl<T, S: BuildHasher> ScopeSet<T, S> {
   pub fn pop_layer(&mut self) -> bool {
     self.map.pop_layer()
   }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys.
+  /// Returns the keys defined in the removed layer that were never read, or `None` if no layer was removed.
+  ///
+  /// While read tracking is disabled, no reads are recorded, so the returned list is always empty; see `set_read_tracking()`.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys stored in the removed layer.
+  #[inline]
+  pub fn pop_layer_reporting(&mut self) -> Option<Vec<&T>> {
+    self.map.pop_layer_reporting()
+  }
+
+  /// Returns `true` if read tracking is enabled.
+  #[inline]
+  pub fn is_tracking_reads(&self) -> bool {
+    self.map.is_tracking_reads()
+  }
+
+  /// Enables or disables read tracking.
+  ///
+  /// While enabled, every key found by `contains()`, `get()`, `depth_of_parent()` or `height_of_parent()`
+  /// is marked as used in the layer where it was found.
+  /// Keys that were never marked can be retrieved with `pop_layer_reporting()`.
+  #[inline]
+  pub fn set_read_tracking(&mut self, enabled: bool) {
+    self.map.set_read_tracking(enabled)
+  }
 }
 
-impl<T: Eq + Hash, S: BuildHasher> ScopeSet<T, S> {
+impl<T: Eq + Hash, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeSet<T, S, STACK, LAYERS> {
+  /// Reserves capacity for at least `additional` more keys.
+  #[inline]
+  pub fn reserve(&mut self, additional: usize) {
+    self.map.reserve(additional)
+  }
+
+  /// Reserves capacity for at least `additional` more layers.
+  #[inline]
+  pub fn reserve_layers(&mut self, additional: usize) {
+    self.map.reserve_layers(additional)
+  }
+
+  /// Reserves capacity for at least `additional` more keys to be defined in the topmost layer.
+  #[inline]
+  pub fn reserve_in_top(&mut self, additional: usize) {
+    self.map.reserve_in_top(additional)
+  }
+
+  /// Removes keys that are no longer in any layer from the key table,
+  /// then shrinks the key table, key stacks and layers as much as possible.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys and layers.
+  #[inline]
+  pub fn shrink_to_fit(&mut self) {
+    self.map.shrink_to_fit()
+  }
+
   /// Removes all entries and additional layers. 
   #[inline]
   pub fn clear_all(&mut self) {
@@ -110,35 +324,118 @@ impl<T: Eq + Hash, S: BuildHasher> ScopeSet<T, S> {
   }
 
   /// Adds the specified key to the topmost layer.
+  ///
+  /// Replaces any existing binding of the key in the topmost layer, regardless of the redefinition policy; use `try_define()` to apply it.
   #[inline]
   pub fn define(&mut self, key: T) {
     self.map.define(key, ());
   }
 
+  /// Adds the specified key to the topmost layer and reports how it relates to existing definitions of the key.
+  ///
+  /// If the key is already defined in the topmost layer, the set's [`RedefinitionPolicy`] decides the result.
+  /// See [`ScopeMap::try_define`] for details.
+  #[inline]
+  pub fn try_define(&mut self, key: T) -> Result<DefineOutcome<()>, RedefinitionError<T, ()>> {
+    self.map.try_define(key, ())
+  }
+
+  /// Adds the specified key to the topmost layer.
+  /// If the key is also defined in a lower layer, returns the height of the highest such layer.
+  #[inline]
+  pub fn define_reporting(&mut self, key: T) -> Option<usize> {
+    self.map.define_reporting(key, ()).map(|info| info.height)
+  }
+
   /// Adds the specified key to the layer `min_depth` layers below the top layer. Saturates to base layer.
   #[inline]
   pub fn define_parent(&mut self, key: T, min_depth: usize) {
     self.map.define_parent(key, (), min_depth);
   }
 
-  /// Removes the specified key from the topmost layer and returns it.
+  /// Adds the specified key to the layer identified by `id`.
+  ///
+  /// Returns `false` if the layer no longer exists.
+  #[inline]
+  pub fn define_in(&mut self, id: LayerId, key: T) -> bool {
+    self.map.define_in(id, key, ())
+  }
+
+  /// Adds all of the specified keys to the topmost layer.
+  ///
+  /// Space for the keys is reserved up front, based on the iterator's lower size hint.
+  /// Keys that appear more than once are only added once.
+  #[inline]
+  pub fn define_many(&mut self, keys: impl IntoIterator<Item = T>) {
+    self.map.define_many(keys.into_iter().map(|key| (key, ())))
+  }
+
+  /// Adds a new layer containing the specified keys.
+  #[inline]
+  pub fn push_layer_from(&mut self, keys: impl IntoIterator<Item = T>) {
+    self.map.push_layer_from(keys.into_iter().map(|key| (key, ())))
+  }
+
+  /// Stores the specified key in place of the equal key already in the set, returning the previously stored key.
+  /// If no equal key is visible, adds the key to the topmost layer and returns `None`.
+  ///
+  /// Like `HashSet::replace()`, this does not change which layers contain the key.
+  #[inline]
+  pub fn replace(&mut self, key: T) -> Option<T> {
+    match self.map.replace_visible_key(key) {
+      Ok(previous_key) => Some(previous_key),
+      Err(key) => {
+        self.map.define(key, ());
+        None
+      },
+    }
+  }
+
+  /// Removes the specified key from every layer and returns the stored key equal to it.
+  ///
+  /// Like `HashSet::take()`, this hands over ownership of the stored key.
+  ///
+  /// Computes in **O(n)** time in relation to the number of layers containing the key.
   #[inline]
-  pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> bool
+  pub fn take<Q>(&mut self, key: &Q) -> Option<T>
   where
     T: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.remove_key_entry(key).map(|(key, _)| key)
+  }
+
+  /// Removes the specified key from the topmost layer.
+  /// Returns `true` if the key was removed.
+  #[inline]
+  pub fn remove<Q>(&mut self, key: &Q) -> bool
+  where
+    T: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
   {
     self.map.remove(key).is_some()
   }
 
+  /// Gets a reference to the stored key equal to the specified key, if it is visible.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get<Q>(&self, key: &Q) -> Option<&T>
+  where
+    T: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.get_key_value(key).map(|(key, _)| key)
+  }
+
   /// Returns `true` if any layer contains the specified key.
   ///
   /// Computes in **O(1)** time.
   #[inline]
-  pub fn contains<Q: ?Sized>(&self, key: &Q) -> bool
+  pub fn contains<Q>(&self, key: &Q) -> bool
   where
     T: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
   {
     self.map.contains_key(key)
   }
@@ -147,25 +444,51 @@ impl<T: Eq + Hash, S: BuildHasher> ScopeSet<T, S> {
   //
   /// Computes in **O(1)** time.
   #[inline]
-  pub fn contains_at_top<Q: ?Sized>(&self, key: &Q) -> bool 
+  pub fn contains_at_top<Q>(&self, key: &Q) -> bool 
   where
     T: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
   {
     self.map.contains_key_at_top(key)
   }
 
+  /// Returns `true` if the layer exactly `depth` layers below the top contains the specified key.
+  /// A depth of 0 refers to the top layer.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers containing the key.
+  #[inline]
+  pub fn contains_at_depth<Q>(&self, key: &Q, depth: usize) -> bool
+  where
+    T: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.contains_key_at_depth(key, depth)
+  }
+
+  /// Returns `true` if the layer at exactly `height` contains the specified key.
+  /// A height of 0 refers to the bottom layer.
+  ///
+  /// Computes in **O(log n)** time in relation to the number of layers containing the key.
+  #[inline]
+  pub fn contains_at_height<Q>(&self, key: &Q, height: usize) -> bool
+  where
+    T: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.contains_key_at_height(key, height)
+  }
+
   /// Gets the depth of the specified key (i.e. how many layers down from the top that the key first appears).
   /// A depth of 0 refers to the top layer.
   ///
   /// Returns `None` if the key does not exist.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to layer count.
+  /// Computes in **O(1)** time.
   #[inline]
-  pub fn depth_of<Q: ?Sized>(&self, key: &Q) -> Option<usize> 
+  pub fn depth_of<Q>(&self, key: &Q) -> Option<usize> 
   where
     T: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
   {
     self.map.depth_of(key)
   }
@@ -176,12 +499,12 @@ impl<T: Eq + Hash, S: BuildHasher> ScopeSet<T, S> {
   ///
   /// Returns `None` if the key does not exist.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to layer count.
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
   #[inline]
-  pub fn depth_of_parent<Q: ?Sized>(&self, key: &Q, min_depth: usize) -> Option<usize> 
+  pub fn depth_of_parent<Q>(&self, key: &Q, min_depth: usize) -> Option<usize> 
   where
     T: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
   {
     self.map.get_parent_depth(key, min_depth).map(|(_, d)| d)
   }
@@ -191,12 +514,12 @@ impl<T: Eq + Hash, S: BuildHasher> ScopeSet<T, S> {
   ///
   /// Returns `None` if the key does not exist.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to layer count.
+  /// Computes in **O(1)** time.
   #[inline]
-  pub fn height_of<Q: ?Sized>(&self, key: &Q) -> Option<usize> 
+  pub fn height_of<Q>(&self, key: &Q) -> Option<usize> 
   where
   T: Borrow<Q>,
-  Q: Eq + Hash,
+  Q: ?Sized + Eq + Hash,
   {
     self.map.height_of(key)
   }
@@ -208,12 +531,12 @@ impl<T: Eq + Hash, S: BuildHasher> ScopeSet<T, S> {
   ///
   /// Returns `None` if the key does not exist.
   ///
-  /// Computes in **O(n)** time (worst-case) in relation to layer count.
+  /// Computes in **O(log n)** time (worst-case) in relation to the number of layers containing the key.
   #[inline]
-  pub fn height_of_parent<Q: ?Sized>(&self, key: &Q, min_depth: usize) -> Option<usize> 
+  pub fn height_of_parent<Q>(&self, key: &Q, min_depth: usize) -> Option<usize> 
   where
     T: Borrow<Q>,
-    Q: Eq + Hash,
+    Q: ?Sized + Eq + Hash,
   {
     self.map.get_parent_height(key, min_depth).map(|(_, h)| h)
   }
@@ -233,11 +556,21 @@ impl<T: Eq + Hash, S: BuildHasher> ScopeSet<T, S> {
   pub fn iter_top(&self) -> impl Iterator<Item = &T> {
     self.map.keys_top()
   }
+
+  /// Iterates over the top-level keys that are also defined in a lower layer, in arbitrary order.
+  /// Each key is paired with the height of the highest lower layer containing it.
+  ///
+  /// The iterator element type is `(&'a T, usize)`.
+  #[inline]
+  pub fn shadowed_in_top(&self) -> impl Iterator<Item = (&T, usize)> {
+    self.map.shadowed_in_top().map(|(key, info)| (key, info.height))
+  }
 }
 
 #[cfg(test)]
 mod test {
   use super::*;
+  use crate::ScopeObserver;
   use std::collections::HashSet;
 
   #[test]
@@ -262,6 +595,19 @@ mod test {
     assert_eq!(32, set.capacity());
   }
 
+  #[test]
+  fn set_inline_capacity() {
+    let mut set: ScopeSet<&str, RandomState, 4, 8> = ScopeSet::configured_with_capacity_and_hasher(16, RandomState::new());
+    assert!(set.capacity() >= 16);
+    set.define("foo");
+    set.with_layer(|set| {
+      set.define("bar");
+      assert_eq!(Some(1), set.height_of("bar"));
+    });
+    assert!(!set.contains("bar"));
+    assert_eq!(Some(0), set.height_of("foo"));
+  }
+
   #[test]
   fn set_define() {
     let mut set = ScopeSet::new();
@@ -278,6 +624,129 @@ mod test {
     assert!(set.contains("foo"));
   }
 
+  #[test]
+  fn set_try_define() {
+    let mut set = ScopeSet::new();
+    assert_eq!(Ok(DefineOutcome::New), set.try_define("foo"));
+    set.push_layer();
+    assert_eq!(Ok(DefineOutcome::Shadowed { height: 0 }), set.try_define("foo"));
+    assert_eq!(Ok(DefineOutcome::Replaced(())), set.try_define("foo"));
+  }
+
+  #[test]
+  fn set_try_define_reject() {
+    let mut set = ScopeSet::new();
+    set.set_redefinition_policy(RedefinitionPolicy::Reject);
+    set.define("foo");
+    assert!(set.try_define("foo").is_err());
+    assert_eq!(1, set.len());
+  }
+
+  #[test]
+  fn set_define_reporting() {
+    let mut set = ScopeSet::new();
+    assert_eq!(None, set.define_reporting("foo"));
+    set.push_layer();
+    assert_eq!(Some(0), set.define_reporting("foo"));
+    assert_eq!(vec![(&"foo", 0)], set.shadowed_in_top().collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn set_pop_layer_reporting() {
+    let mut set = ScopeSet::new();
+    set.set_read_tracking(true);
+    set.push_layer();
+    set.define("foo");
+    set.define("bar");
+    assert!(set.contains("foo"));
+    assert_eq!(Some(vec![&"bar"]), set.pop_layer_reporting());
+  }
+
+  #[test]
+  fn set_scope_guard() {
+    let mut set = ScopeSet::new();
+    {
+      let mut scope = set.scope();
+      scope.define("foo");
+      assert!(scope.contains("foo"));
+    }
+    assert_eq!(1, set.depth());
+    assert!(!set.contains("foo"));
+    assert!(set.with_layer(|set| {
+      set.define("bar");
+      set.contains("bar")
+    }));
+    assert!(!set.contains("bar"));
+  }
+
+  #[test]
+  fn set_with_layer_meta() {
+    let mut set = ScopeSet::new();
+    set.define("foo");
+    let inner = set.with_layer_meta("loop", |set| {
+      set.define("bar");
+      set.push_layer();
+      (set.layer_meta::<&str>(1).copied(), set.find_layer_meta::<&str>().map(|(_, depth)| depth))
+    });
+    assert_eq!((Some("loop"), Some(1)), inner);
+    assert_eq!(None, set.find_layer_meta::<&str>());
+    assert!(!set.contains("bar"));
+  }
+
+  #[test]
+  fn set_layer_ids() {
+    let mut set = ScopeSet::new();
+    let outer = set.push_layer_id();
+    let inner = set.push_layer_id();
+    assert!(set.define_in(outer, "foo"));
+    assert_eq!(Some(1), set.height_of("foo"));
+    assert!(set.pop_layer_checked(outer).is_err());
+    assert_eq!(Ok(()), set.pop_layer_checked(inner));
+    assert_eq!(Ok(()), set.pop_layer_checked(outer));
+    assert!(!set.define_in(outer, "foo"));
+    assert!(!set.contains("foo"));
+  }
+
+  #[test]
+  fn set_observe() {
+    #[derive(Default)]
+    struct Counter {
+      defines: usize,
+      shadows: usize,
+      popped: Vec<&'static str>,
+    }
+
+    impl ScopeObserver<&'static str, ()> for Counter {
+      fn on_define(&mut self, _key: &&'static str, _value: &(), _height: usize) {
+        self.defines += 1;
+      }
+
+      fn on_shadow(&mut self, _key: &&'static str, _height: usize, _shadowed_height: usize) {
+        self.shadows += 1;
+      }
+
+      fn on_pop_layer(&mut self, _height: usize, removed_keys: &[&&'static str]) {
+        self.popped.extend(removed_keys.iter().map(|key| **key));
+      }
+    }
+
+    let mut set = ScopeSet::new().observe(Counter::default());
+    set.define("foo");
+    set.push_layer();
+    set.define("foo");
+    assert!(set.contains("foo"));
+    set.pop_layer();
+    set.set_read_tracking(true);
+    set.push_layer_from(vec!["bar", "baz"]);
+    assert!(set.try_define("bar").is_ok());
+    assert!(set.contains("bar"));
+    assert_eq!(Some(vec![&"baz"]), set.pop_layer_reporting());
+    set.clear_all();
+    let (set, counter) = set.into_inner();
+    assert_eq!(0, set.len());
+    assert_eq!((5, 1, vec!["foo", "bar", "baz"]), (counter.defines, counter.shadows, counter.popped));
+  }
+
   #[test]
   fn set_delete() {
     let mut set = ScopeSet::new();
@@ -306,6 +775,7 @@ mod test {
   }
 
   #[test]
+  #[allow(clippy::bool_assert_comparison)]
   fn set_try_pop_first_layer() {
     let mut set: ScopeSet<String> = Default::default();
     assert_eq!(false, set.pop_layer());
@@ -336,6 +806,22 @@ mod test {
     assert!(set.contains("bar"));
   }
 
+  #[test]
+  fn set_contains_at_height() {
+    let mut set = ScopeSet::new();
+    set.define("foo");
+    set.push_layer();
+    set.push_layer();
+    set.define("foo");
+    assert!(set.contains_at_height("foo", 0));
+    assert!(!set.contains_at_height("foo", 1));
+    assert!(set.contains_at_height("foo", 2));
+    assert!(set.contains_at_depth("foo", 0));
+    assert!(!set.contains_at_depth("foo", 1));
+    assert!(set.contains_at_depth("foo", 2));
+    assert!(!set.contains_at_depth("foo", usize::MAX));
+  }
+
   #[test]
   fn set_depth_of() {
     let mut set = ScopeSet::new();
@@ -410,4 +896,73 @@ mod test {
     let actual_keys: HashSet<&str> = set.iter_top().cloned().collect();
     assert_eq!(expected_keys, actual_keys);
   }
+
+  #[test]
+  fn set_reserve_and_shrink() {
+    let mut set = ScopeSet::new();
+    set.reserve(64);
+    set.reserve_in_top(16);
+    set.define("foo");
+    set.push_layer();
+    set.define("foo");
+    let stats = set.memory_stats();
+    assert!(stats.key_capacity >= 64);
+    assert_eq!(1, stats.key_count);
+    assert_eq!(2, stats.stack_entries);
+    set.pop_layer();
+    set.shrink_to_fit();
+    assert_eq!(0, set.memory_stats().spilled_stacks);
+    assert!(set.contains("foo"));
+  }
+
+  #[test]
+  fn set_interning() {
+    use std::rc::Rc;
+    let mut set: ScopeSet<Rc<str>> = ScopeSet::new();
+    let first: Rc<str> = Rc::from("foo");
+    set.define(first.clone());
+    set.push_layer();
+    let second: Rc<str> = Rc::from("foo");
+    assert!(Rc::ptr_eq(&first, &set.replace(second.clone()).unwrap()));
+    assert!(Rc::ptr_eq(&second, set.get("foo").unwrap()));
+    assert!(!set.contains_at_top("foo"));
+    assert_eq!(None, set.get("bar"));
+    assert_eq!(None, set.replace(Rc::from("bar")));
+    assert_eq!(Some(1), set.height_of("bar"));
+  }
+
+  #[test]
+  fn set_take() {
+    let mut set = ScopeSet::new();
+    set.set_read_tracking(true);
+    set.define(String::from("foo"));
+    set.define(String::from("bar"));
+    set.push_layer();
+    set.define(String::from("foo"));
+    set.define(String::from("baz"));
+    assert!(set.contains("baz"));
+    assert_eq!(Some(String::from("foo")), set.take("foo"));
+    assert_eq!(None, set.take("foo"));
+    assert!(!set.contains("foo"));
+    assert_eq!(2, set.len());
+    assert_eq!(Some(0), set.height_of("bar"));
+    assert_eq!(Some(1), set.height_of("baz"));
+    set.define(String::from("foo"));
+    assert_eq!(Some(1), set.height_of("foo"));
+    assert_eq!(Some(vec![&String::from("foo")]), set.pop_layer_reporting());
+    set.map.check_invariants().unwrap();
+  }
+
+  #[test]
+  fn set_define_many() {
+    let mut set = ScopeSet::new();
+    set.define_many(["foo", "bar"]);
+    set.push_layer_from(["baz", "foo", "baz"]);
+    assert_eq!(3, set.len());
+    assert_eq!(2, set.iter_top().count());
+    assert_eq!(Some(1), set.height_of("foo"));
+    set.pop_layer();
+    assert_eq!(Some(0), set.height_of("foo"));
+    assert!(!set.contains("baz"));
+  }
 }
\ No newline at end of file
@@ -0,0 +1,21 @@
+/// A report of the memory used by a [`ScopeMap`](crate::ScopeMap) or [`ScopeSet`](crate::ScopeSet),
+/// created by `memory_stats()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryStats {
+  /// The number of keys in the key table, including keys that currently have no bindings.
+  pub key_count: usize,
+  /// The number of keys the key table can hold without reallocating.
+  pub key_capacity: usize,
+  /// The total number of bindings across all value stacks.
+  pub stack_entries: usize,
+  /// The number of value stacks that no longer fit inline and have spilled to the heap.
+  pub spilled_stacks: usize,
+  /// The number of layers.
+  pub layer_count: usize,
+  /// The number of layers the layer list can hold without reallocating.
+  pub layer_capacity: usize,
+  /// The combined capacity of all layers' index sets.
+  pub layer_set_capacity: usize,
+  /// The total number of slots across all layers, including vacant slots waiting to be reused.
+  pub layer_slot_count: usize,
+}
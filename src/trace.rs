@@ -0,0 +1,423 @@
+use std::{borrow::Borrow, collections::hash_map::RandomState, hash::{BuildHasher, Hash}, ops::{Deref, DerefMut}};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::ScopeMap;
+
+/// A single operation recorded by a [`RecordingScopeMap`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScopeOp<K, V> {
+  /// A call to `define()`.
+  Define(K, V),
+  /// A call to `define_parent()`.
+  DefineParent(K, V, usize),
+  /// A call to `remove()`.
+  Remove(K),
+  /// A call to `push_layer()`.
+  PushLayer,
+  /// A call to `pop_layer()`.
+  PopLayer,
+  /// A call to `clear_top()`.
+  ClearTop,
+}
+
+impl<K: Eq + Hash, V> ScopeOp<K, V> {
+  /// Performs the operation on the specified map.
+  #[inline]
+  pub fn apply<S: BuildHasher, const STACK: usize, const LAYERS: usize>(self, map: &mut ScopeMap<K, V, S, STACK, LAYERS>) {
+    match self {
+      ScopeOp::Define(key, value) => map.define(key, value),
+      ScopeOp::DefineParent(key, value, min_depth) => map.define_parent(key, value, min_depth),
+      ScopeOp::Remove(key) => {
+        map.remove(&key);
+      },
+      ScopeOp::PushLayer => map.push_layer(),
+      ScopeOp::PopLayer => {
+        map.pop_layer();
+      },
+      ScopeOp::ClearTop => map.clear_top(),
+    }
+  }
+}
+
+/// A sequence of operations recorded by a [`RecordingScopeMap`], starting from an empty map.
+///
+/// With the `serde` feature enabled, traces can be serialized and sent elsewhere for replay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScopeTrace<K, V> {
+  ops: Vec<ScopeOp<K, V>>,
+}
+
+impl<K, V> Default for ScopeTrace<K, V> {
+  #[inline]
+  fn default() -> Self {
+    Self {
+      ops: vec![]
+    }
+  }
+}
+
+impl<K, V> From<Vec<ScopeOp<K, V>>> for ScopeTrace<K, V> {
+  #[inline]
+  fn from(ops: Vec<ScopeOp<K, V>>) -> Self {
+    Self {
+      ops
+    }
+  }
+}
+
+impl<K, V> ScopeTrace<K, V> {
+  /// Gets the recorded operations in order.
+  #[inline]
+  pub fn ops(&self) -> &[ScopeOp<K, V>] {
+    &self.ops
+  }
+
+  /// Gets the number of recorded operations.
+  #[inline]
+  pub fn len(&self) -> usize {
+    self.ops.len()
+  }
+
+  /// Returns `true` if no operations were recorded.
+  #[inline]
+  pub fn is_empty(&self) -> bool {
+    self.ops.is_empty()
+  }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> ScopeTrace<K, V> {
+  /// Rebuilds the map that produced this trace by performing every operation on an empty map.
+  #[inline]
+  pub fn replay(&self) -> ScopeMap<K, V> {
+    self.replay_with_hasher(Default::default())
+  }
+
+  /// Rebuilds the map that produced this trace by performing every operation on an empty map with the specified hasher.
+  #[inline]
+  pub fn replay_with_hasher<S: BuildHasher>(&self, hash_builder: S) -> ScopeMap<K, V, S> {
+    let mut map = ScopeMap::with_hasher(hash_builder);
+    self.replay_into(&mut map);
+    map
+  }
+
+  /// Performs every operation of the trace on `map`, which rebuilds the recorded map if `map` is empty.
+  ///
+  /// Unlike [`ScopeTrace::replay_with_hasher`], this works for any `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn replay_into<S: BuildHasher, const STACK: usize, const LAYERS: usize>(&self, map: &mut ScopeMap<K, V, S, STACK, LAYERS>) {
+    for op in &self.ops {
+      op.clone().apply(map);
+    }
+  }
+
+  /// Replays the trace one operation at a time, allowing the map to be inspected after each one.
+  #[inline]
+  pub fn steps(&self) -> ReplaySteps<'_, K, V> {
+    self.steps_with_hasher(Default::default())
+  }
+
+  /// Replays the trace one operation at a time on an empty map with the specified hasher.
+  #[inline]
+  pub fn steps_with_hasher<S: BuildHasher>(&self, hash_builder: S) -> ReplaySteps<'_, K, V, S> {
+    ReplaySteps {
+      ops: self.ops.iter(),
+      map: ScopeMap::with_hasher(hash_builder),
+    }
+  }
+}
+
+/// Replays a [`ScopeTrace`] one operation at a time, created by [`ScopeTrace::steps`].
+pub struct ReplaySteps<'a, K, V, S: BuildHasher = RandomState> {
+  ops: std::slice::Iter<'a, ScopeOp<K, V>>,
+  map: ScopeMap<K, V, S>,
+}
+
+impl<'a, K: Clone + Eq + Hash, V: Clone, S: BuildHasher> ReplaySteps<'a, K, V, S> {
+  /// Gets the operation that the next call to `step()` will perform.
+  #[inline]
+  pub fn peek(&self) -> Option<&'a ScopeOp<K, V>> {
+    self.ops.as_slice().first()
+  }
+
+  /// Performs the next operation and returns the resulting map state.
+  /// Returns `None` once every operation has been performed.
+  #[inline]
+  pub fn step(&mut self) -> Option<&ScopeMap<K, V, S>> {
+    self.ops.next()?.clone().apply(&mut self.map);
+    Some(&self.map)
+  }
+
+  /// Gets the map state after the operations performed so far.
+  #[inline]
+  pub fn map(&self) -> &ScopeMap<K, V, S> {
+    &self.map
+  }
+
+  /// Consumes the replay and returns the map state after the operations performed so far.
+  #[inline]
+  pub fn into_map(self) -> ScopeMap<K, V, S> {
+    self.map
+  }
+}
+
+/// A [`ScopeMap`] that records every change made through it to a [`ScopeTrace`].
+///
+/// The wrapper dereferences to the underlying map for read access;
+/// changes must go through the wrapper's own methods so that they are recorded.
+#[derive(Clone)]
+pub struct RecordingScopeMap<K, V, S: BuildHasher = RandomState, const STACK: usize = 1, const LAYERS: usize = 1> {
+  map: ScopeMap<K, V, S, STACK, LAYERS>,
+  trace: ScopeTrace<K, V>,
+}
+
+impl<K, V, S: Default + BuildHasher, const STACK: usize, const LAYERS: usize> Default for RecordingScopeMap<K, V, S, STACK, LAYERS> {
+  /// Creates a new, empty `RecordingScopeMap` with the default configuration.
+  #[inline]
+  fn default() -> Self {
+    Self {
+      map: Default::default(),
+      trace: Default::default(),
+    }
+  }
+}
+
+impl<K, V> RecordingScopeMap<K, V, RandomState> {
+  /// Creates an empty `RecordingScopeMap` with a default hasher and capacity.
+  #[inline]
+  pub fn new() -> Self {
+    Default::default()
+  }
+}
+
+impl<K, V, S: BuildHasher> RecordingScopeMap<K, V, S> {
+  /// Creates an empty `RecordingScopeMap` with the specified hasher.
+  #[inline]
+  pub fn with_hasher(hash_builder: S) -> Self {
+    Self::configured_with_hasher(hash_builder)
+  }
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> RecordingScopeMap<K, V, S, STACK, LAYERS> {
+  /// Creates an empty `RecordingScopeMap` with the specified hasher, using the inline capacities given by the type.
+  ///
+  /// Unlike [`RecordingScopeMap::with_hasher`], this works for any `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn configured_with_hasher(hash_builder: S) -> Self {
+    Self {
+      map: ScopeMap::configured_with_hasher(hash_builder),
+      trace: Default::default(),
+    }
+  }
+
+  /// Gets the operations recorded so far.
+  #[inline]
+  pub fn trace(&self) -> &ScopeTrace<K, V> {
+    &self.trace
+  }
+
+  /// Consumes the wrapper and returns the map along with the recorded trace.
+  #[inline]
+  pub fn into_parts(self) -> (ScopeMap<K, V, S, STACK, LAYERS>, ScopeTrace<K, V>) {
+    (self.map, self.trace)
+  }
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> Deref for RecordingScopeMap<K, V, S, STACK, LAYERS> {
+  type Target = ScopeMap<K, V, S, STACK, LAYERS>;
+
+  #[inline]
+  fn deref(&self) -> &Self::Target {
+    &self.map
+  }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone, S: BuildHasher, const STACK: usize, const LAYERS: usize> RecordingScopeMap<K, V, S, STACK, LAYERS> {
+  /// Adds a new, empty layer.
+  #[inline]
+  pub fn push_layer(&mut self) {
+    self.trace.ops.push(ScopeOp::PushLayer);
+    self.map.push_layer()
+  }
+
+  /// Removes the topmost layer (if it isn't the bottom layer) and all associated keys/values.
+  /// Returns `true` if a layer was removed.
+  #[inline]
+  pub fn pop_layer(&mut self) -> bool {
+    self.trace.ops.push(ScopeOp::PopLayer);
+    self.map.pop_layer()
+  }
+
+  /// Adds the specified entry to the topmost layer.
+  #[inline]
+  pub fn define(&mut self, key: K, value: V) {
+    self.trace.ops.push(ScopeOp::Define(key.clone(), value.clone()));
+    self.map.define(key, value)
+  }
+
+  /// Adds the specified entry in the layer `min_depth` layers from the top. Saturates to base layer.
+  #[inline]
+  pub fn define_parent(&mut self, key: K, value: V, min_depth: usize) {
+    self.trace.ops.push(ScopeOp::DefineParent(key.clone(), value.clone(), min_depth));
+    self.map.define_parent(key, value, min_depth)
+  }
+
+  /// Removes the entry with the specified key from the topmost layer and returns its value.
+  ///
+  /// Removing a key that is not defined in the topmost layer has no effect, and is not recorded.
+  #[inline]
+  pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    if self.map.contains_key_at_top(key) {
+      let index = self.map.index_of_equivalent(key).unwrap();
+      self.trace.ops.push(ScopeOp::Remove(self.map.stack_at(index).0.clone()));
+    }
+    self.map.remove(key)
+  }
+
+  /// Gets a mutable reference to the topmost value associated with a key.
+  ///
+  /// When the returned guard is dropped, the binding's value is recorded as a `Define` (or a `DefineParent`, for a binding below the topmost layer)
+  /// that replaces the binding in its layer.
+  #[inline]
+  pub fn get_mut<Q>(&mut self, key: &Q) -> Option<RecordingMut<'_, K, V>>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let index = self.map.index_of_equivalent(key)?;
+    let depth = self.map.depth_of(key)?;
+    let recorded_key = self.map.stack_at(index).0.clone();
+    Some(RecordingMut {
+      key: Some(recorded_key),
+      depth,
+      value: self.map.get_mut(key)?,
+      ops: &mut self.trace.ops,
+    })
+  }
+
+  /// Removes all entries in the topmost layer.
+  #[inline]
+  pub fn clear_top(&mut self) {
+    self.trace.ops.push(ScopeOp::ClearTop);
+    self.map.clear_top()
+  }
+}
+
+/// A mutable reference to a value in a [`RecordingScopeMap`], created by [`RecordingScopeMap::get_mut`].
+///
+/// The value is recorded to the trace when the guard is dropped.
+pub struct RecordingMut<'a, K: Clone, V: Clone> {
+  key: Option<K>,
+  depth: usize,
+  value: &'a mut V,
+  ops: &'a mut Vec<ScopeOp<K, V>>,
+}
+
+impl<'a, K: Clone, V: Clone> Deref for RecordingMut<'a, K, V> {
+  type Target = V;
+
+  #[inline]
+  fn deref(&self) -> &V {
+    self.value
+  }
+}
+
+impl<'a, K: Clone, V: Clone> DerefMut for RecordingMut<'a, K, V> {
+  #[inline]
+  fn deref_mut(&mut self) -> &mut V {
+    self.value
+  }
+}
+
+impl<'a, K: Clone, V: Clone> Drop for RecordingMut<'a, K, V> {
+  #[inline]
+  fn drop(&mut self) {
+    let key = self.key.take().unwrap();
+    let value = self.value.clone();
+    self.ops.push(match self.depth {
+      0 => ScopeOp::Define(key, value),
+      depth => ScopeOp::DefineParent(key, value, depth),
+    });
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::{collections::{hash_map::DefaultHasher, HashSet}, hash::BuildHasherDefault};
+
+  fn snapshot(map: &ScopeMap<&'static str, i32>) -> (usize, HashSet<(&'static str, i32, usize)>) {
+    let entries = map
+      .iter()
+      .map(|(key, value)| (*key, *value, map.height_of(key).unwrap()))
+      .collect();
+    (map.depth(), entries)
+  }
+
+  #[test]
+  fn trace_replay() {
+    let mut map = RecordingScopeMap::new();
+    map.define("foo", 1);
+    map.push_layer();
+    map.define("foo", 2);
+    map.define_parent("bar", 3, 1);
+    map.push_layer();
+    map.define("baz", 4);
+    map.remove("baz");
+    map.remove("qux");
+    map.define("qux", 5);
+    map.clear_top();
+    map.define("qux", 6);
+    map.remove("foo");
+    *map.get_mut("foo").unwrap() += 10;
+    *map.get_mut("qux").unwrap() += 10;
+    assert!(map.get_mut("quux").is_none());
+    map.pop_layer();
+
+    let (map, trace) = map.into_parts();
+    assert_eq!(ScopeOp::DefineParent("foo", 12, 1), trace.ops()[10]);
+    assert_eq!(ScopeOp::Define("qux", 16), trace.ops()[11]);
+    assert_eq!(13, trace.len());
+    assert_eq!(snapshot(&map), snapshot(&trace.replay()));
+  }
+
+  #[test]
+  fn trace_steps() {
+    let trace = ScopeTrace::from(vec![
+      ScopeOp::Define("foo", 1),
+      ScopeOp::PushLayer,
+      ScopeOp::Define("foo", 2),
+      ScopeOp::PopLayer,
+    ]);
+    let mut steps = trace.steps();
+    assert_eq!(Some(&ScopeOp::Define("foo", 1)), steps.peek());
+    let mut seen = vec![];
+    while let Some(map) = steps.step() {
+      seen.push((map.depth(), map.get("foo").copied()));
+    }
+    assert_eq!(vec![(1, Some(1)), (2, Some(1)), (2, Some(2)), (1, Some(1))], seen);
+  }
+
+  #[test]
+  fn trace_configured() {
+    let mut map: RecordingScopeMap<&str, i32, BuildHasherDefault<DefaultHasher>, 4, 8> = RecordingScopeMap::configured_with_hasher(Default::default());
+    map.define("foo", 1);
+    map.push_layer();
+    map.define("foo", 2);
+    let (_, trace) = map.into_parts();
+    let replayed = trace.replay_with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+    assert_eq!(Some((&2, 1)), replayed.get_parent_height("foo", 0));
+    let mut configured: ScopeMap<&str, i32, BuildHasherDefault<DefaultHasher>, 4, 8> = ScopeMap::configured_with_hasher(Default::default());
+    trace.replay_into(&mut configured);
+    assert_eq!(Some(&1), configured.get_parent("foo", 1));
+    let mut steps = trace.steps_with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+    assert_eq!(Some(1), steps.step().map(|map| map.len()));
+  }
+}
@@ -0,0 +1,290 @@
+use std::{borrow::Borrow, collections::{hash_map::RandomState, HashMap, HashSet}, hash::{BuildHasher, Hash}};
+
+use crate::ScopeMap;
+
+/// Identifies a scope in a [`ScopeTree`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScopeId(usize);
+
+impl ScopeId {
+  /// The ID of the root scope, which every `ScopeTree` starts with.
+  pub const ROOT: ScopeId = ScopeId(0);
+}
+
+#[derive(Clone)]
+struct ScopeNode<V> {
+  parent: Option<ScopeId>,
+  /// Maps key indices in the tree's `ScopeMap` to the values defined in this scope.
+  bindings: HashMap<usize, V>,
+}
+
+/// A scope stack that retains popped scopes as a tree, for answering name resolution queries after the fact.
+///
+/// While walking a syntax tree, a `ScopeTree` is used like a [`ScopeMap`]: `push_layer()` enters a new child scope,
+/// `pop_layer()` returns to its parent, and `get()` resolves keys against the current scope in **O(1)** time.
+/// Unlike a `ScopeMap`, popped scopes are kept, and can later be queried by [`ScopeId`] with `resolve_at()` and `visible_at()`.
+#[derive(Clone)]
+pub struct ScopeTree<K, V, S: BuildHasher = RandomState, const STACK: usize = 1, const LAYERS: usize = 1> {
+  /// Tracks the visible bindings of the current scope; each value is the scope that owns the binding.
+  map: ScopeMap<K, ScopeId, S, STACK, LAYERS>,
+  nodes: Vec<ScopeNode<V>>,
+  current: ScopeId,
+}
+
+impl<K, V, S: Default + BuildHasher, const STACK: usize, const LAYERS: usize> Default for ScopeTree<K, V, S, STACK, LAYERS> {
+  /// Creates a new `ScopeTree` with the default configuration.
+  #[inline]
+  fn default() -> Self {
+    Self::configured_with_hasher(Default::default())
+  }
+}
+
+impl<K, V> ScopeTree<K, V, RandomState> {
+  /// Creates a `ScopeTree` containing only an empty root scope.
+  #[inline]
+  pub fn new() -> Self {
+    Default::default()
+  }
+}
+
+impl<K, V, S: BuildHasher> ScopeTree<K, V, S> {
+  /// Creates a `ScopeTree` containing only an empty root scope, with the specified hasher.
+  #[inline]
+  pub fn with_hasher(hash_builder: S) -> Self {
+    Self::configured_with_hasher(hash_builder)
+  }
+}
+
+impl<K, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeTree<K, V, S, STACK, LAYERS> {
+  /// Creates a `ScopeTree` containing only an empty root scope, with the specified hasher and the inline capacities given by the type.
+  ///
+  /// Unlike [`ScopeTree::with_hasher`], this works for any `STACK` and `LAYERS` configuration.
+  #[inline]
+  pub fn configured_with_hasher(hash_builder: S) -> Self {
+    Self {
+      map: ScopeMap::configured_with_hasher(hash_builder),
+      nodes: vec![ScopeNode { parent: None, bindings: HashMap::new() }],
+      current: ScopeId::ROOT,
+    }
+  }
+
+  /// Gets the ID of the current scope.
+  #[inline]
+  pub fn current(&self) -> ScopeId {
+    self.current
+  }
+
+  /// Gets the ID of the parent of the specified scope, or `None` for the root scope.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `scope` does not belong to this tree.
+  #[inline]
+  pub fn parent(&self, scope: ScopeId) -> Option<ScopeId> {
+    self.nodes[scope.0].parent
+  }
+
+  /// Gets the total number of scopes in the tree, including popped scopes.
+  #[inline]
+  pub fn scope_count(&self) -> usize {
+    self.nodes.len()
+  }
+
+  /// Gets the number of scopes between the current scope and the root, inclusive.
+  #[inline]
+  pub fn depth(&self) -> usize {
+    self.map.depth()
+  }
+
+  /// Creates a new, empty child of the current scope, makes it the current scope, and returns its ID.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn push_layer(&mut self) -> ScopeId {
+    let id = ScopeId(self.nodes.len());
+    self.nodes.push(ScopeNode { parent: Some(self.current), bindings: HashMap::new() });
+    self.map.push_layer();
+    self.current = id;
+    id
+  }
+
+  /// Makes the parent of the current scope the current scope. The popped scope is retained in the tree.
+  /// Returns `false` if the current scope is the root scope.
+  ///
+  /// Computes in **O(n)** time in relation to the number of keys defined in the popped scope.
+  #[inline]
+  pub fn pop_layer(&mut self) -> bool {
+    match self.nodes[self.current.0].parent {
+      Some(parent) => {
+        self.map.pop_layer();
+        self.current = parent;
+        true
+      },
+      None => false,
+    }
+  }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher, const STACK: usize, const LAYERS: usize> ScopeTree<K, V, S, STACK, LAYERS> {
+  /// Adds the specified entry to the current scope.
+  #[inline]
+  pub fn define(&mut self, key: K, value: V) {
+    let index = self.map.define_index(key, self.current);
+    self.nodes[self.current.0].bindings.insert(index, value);
+  }
+
+  /// Returns `true` if the key is visible from the current scope.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    self.map.contains_key(key)
+  }
+
+  /// Gets a reference to the value associated with a key as seen from the current scope.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get<Q>(&self, key: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let (index, _, scope) = self.map.get_full_equivalent(key)?;
+    self.nodes[scope.0].bindings.get(&index)
+  }
+
+  /// Gets a mutable reference to the value associated with a key as seen from the current scope.
+  ///
+  /// Computes in **O(1)** time.
+  #[inline]
+  pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let (index, _, scope) = self.map.get_full_equivalent(key)?;
+    let scope = *scope;
+    self.nodes[scope.0].bindings.get_mut(&index)
+  }
+
+  /// Resolves a key as seen from the specified scope, which may have already been popped.
+  /// Returns the ID of the scope containing the binding, along with its value.
+  ///
+  /// Computes in **O(n)** time (worst-case) in relation to the depth of `scope` in the tree.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `scope` does not belong to this tree.
+  #[inline]
+  pub fn resolve_at<Q>(&self, scope: ScopeId, key: &Q) -> Option<(ScopeId, &V)>
+  where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+  {
+    let index = self.map.index_of_equivalent(key)?;
+    let mut next = Some(scope);
+    while let Some(id) = next {
+      let node = &self.nodes[id.0];
+      if let Some(value) = node.bindings.get(&index) {
+        return Some((id, value))
+      }
+      next = node.parent;
+    }
+    None
+  }
+
+  /// Gets all bindings visible from the specified scope, which may have already been popped.
+  /// Bindings hidden by a binding of the same key in a nearer scope are excluded.
+  ///
+  /// Computes in **O(n)** time in relation to the number of bindings in `scope` and its ancestors.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `scope` does not belong to this tree.
+  #[inline]
+  pub fn visible_at(&self, scope: ScopeId) -> Vec<(&K, &V)> {
+    let mut seen = HashSet::new();
+    let mut visible = vec![];
+    let mut next = Some(scope);
+    while let Some(id) = next {
+      let node = &self.nodes[id.0];
+      for (index, value) in node.bindings.iter() {
+        if seen.insert(*index) {
+          visible.push((self.map.stack_at(*index).0, value));
+        }
+      }
+      next = node.parent;
+    }
+    visible
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn tree_walk_lookup() {
+    let mut tree = ScopeTree::new();
+    tree.define("foo", 1);
+    tree.push_layer();
+    tree.define("foo", 2);
+    assert_eq!(Some(&2), tree.get("foo"));
+    if let Some(foo) = tree.get_mut("foo") {
+      *foo = 3;
+    }
+    tree.pop_layer();
+    assert_eq!(Some(&1), tree.get("foo"));
+    assert!(!tree.pop_layer());
+  }
+
+  #[test]
+  fn tree_resolve_at_popped() {
+    let mut tree = ScopeTree::new();
+    tree.define("foo", 1);
+    let child = tree.push_layer();
+    tree.define("foo", 2);
+    tree.define("bar", 3);
+    tree.pop_layer();
+    let sibling = tree.push_layer();
+    tree.pop_layer();
+
+    assert_eq!(ScopeId::ROOT, tree.current());
+    assert_eq!(Some(ScopeId::ROOT), tree.parent(child));
+    assert_eq!(3, tree.scope_count());
+    assert_eq!(Some((child, &2)), tree.resolve_at(child, "foo"));
+    assert_eq!(Some((ScopeId::ROOT, &1)), tree.resolve_at(sibling, "foo"));
+    assert_eq!(None, tree.resolve_at(sibling, "bar"));
+    assert!(!tree.contains_key("bar"));
+  }
+
+  #[test]
+  fn tree_visible_at() {
+    let mut tree = ScopeTree::new();
+    tree.define("foo", 1);
+    tree.define("bar", 2);
+    let child = tree.push_layer();
+    tree.define("foo", 3);
+    tree.pop_layer();
+
+    let mut visible: Vec<(&str, i32)> = tree.visible_at(child).into_iter().map(|(k, v)| (*k, *v)).collect();
+    visible.sort();
+    assert_eq!(vec![("bar", 2), ("foo", 3)], visible);
+  }
+
+  #[test]
+  fn tree_configured() {
+    let mut tree: ScopeTree<&str, i32, RandomState, 4, 8> = Default::default();
+    tree.define("foo", 1);
+    let child = tree.push_layer();
+    tree.define("foo", 2);
+    tree.pop_layer();
+    assert_eq!(Some((child, &2)), tree.resolve_at(child, "foo"));
+    assert_eq!(Some(&1), tree.get("foo"));
+  }
+}